@@ -0,0 +1,196 @@
+//! Block-to-time index used to resolve timestamps to block numbers.
+//!
+//! Block timestamps never decrease with the block number, so a timestamp can be resolved by
+//! binary search over block headers. Every header looked up is kept in a `BlockTimeIndex`, which
+//! narrows later searches to the blocks between the nearest known neighbours.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Known block timestamps, in unix seconds, by block number.
+#[derive(Debug, Default)]
+pub struct BlockTimeIndex {
+    timestamps: Mutex<BTreeMap<u64, u64>>,
+}
+
+impl BlockTimeIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the timestamp of `block`, if it is known.
+    pub fn get(&self, block: u64) -> Option<u64> {
+        self.timestamps.lock().unwrap().get(&block).copied()
+    }
+
+    /// Records the timestamp of `block`.
+    pub fn insert(&self, block: u64, timestamp: u64) {
+        self.timestamps.lock().unwrap().insert(block, timestamp);
+    }
+
+    pub fn len(&self) -> usize {
+        self.timestamps.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Narrows the search for the first block at or after `timestamp` within `low..=high` to the
+    /// blocks between the known ones on either side of it.
+    pub fn narrow(&self, timestamp: u64, mut low: u64, mut high: u64) -> (u64, u64) {
+        let timestamps = self.timestamps.lock().unwrap();
+        for (&block, &block_timestamp) in timestamps.range(low..=high) {
+            if block_timestamp < timestamp {
+                low = block + 1;
+            } else {
+                high = block;
+                break;
+            }
+        }
+        (low, high)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasource::{Datasource, DatasourceConfig};
+    use crate::error::DatasourceError;
+    use crate::mock_archive::{Endpoint, Failure, MockArchive};
+    use crate::retry::RetryPolicy;
+    use crate::stop::{StopReason, StopSignal};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn test_narrow_uses_known_blocks() {
+        let index = BlockTimeIndex::new();
+        assert_eq!(index.narrow(150, 0, 1000), (0, 1000));
+
+        index.insert(100, 120);
+        index.insert(200, 140);
+        index.insert(300, 160);
+        index.insert(400, 180);
+        assert_eq!(index.narrow(150, 0, 1000), (201, 300));
+        assert_eq!(index.narrow(140, 0, 1000), (101, 200));
+        assert_eq!(index.narrow(100, 0, 1000), (0, 100));
+        assert_eq!(index.narrow(200, 0, 1000), (401, 1000));
+        assert_eq!(index.narrow(150, 250, 280), (250, 280));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_time_range() {
+        // One block every 12 seconds, starting at 1000.
+        let blocks = (0..100u64)
+            .map(|number| {
+                json!({
+                    "header": {"number": number, "timestamp": 1000 + 12 * number},
+                    "logs": [{"logIndex": 0, "transactionIndex": 0, "address": "0x01"}],
+                })
+            })
+            .collect();
+        let server = MockArchive::new(blocks)
+            .with_worker_range_size(10)
+            .start()
+            .await
+            .unwrap();
+        let datasource = Datasource::new(DatasourceConfig::new(server.url(), 2));
+
+        assert_eq!(datasource.get_block_at_time(1030).await.unwrap(), Some(3));
+        assert_eq!(datasource.get_block_at_time(1036).await.unwrap(), Some(3));
+        assert_eq!(datasource.get_block_at_time(0).await.unwrap(), Some(0));
+        assert_eq!(datasource.get_block_at_time(5000).await.unwrap(), None);
+        assert_eq!(
+            datasource
+                .get_block_range_between(1030, 1100)
+                .await
+                .unwrap(),
+            Some((3, 8))
+        );
+        assert_eq!(
+            datasource
+                .get_block_range_between(2140, 9999)
+                .await
+                .unwrap(),
+            Some((95, 99))
+        );
+        assert_eq!(
+            datasource
+                .get_block_range_between(1001, 1011)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // Resolved blocks are served from the index.
+        let requests = server.stats().query_requests;
+        assert_eq!(
+            datasource
+                .get_block_range_between(1030, 1100)
+                .await
+                .unwrap(),
+            Some((3, 8))
+        );
+        assert_eq!(server.stats().query_requests, requests);
+
+        let query = json!({"logs": [{}], "fields": {"log": {"address": true}}});
+        let df = datasource
+            .get_as_df_between(query.clone(), 1030, 1110)
+            .await
+            .unwrap();
+        assert_eq!(df.height(), 7);
+        let df = datasource
+            .get_as_df_between(query.clone(), 1001, 1011)
+            .await
+            .unwrap();
+        assert_eq!(df.height(), 0);
+
+        let fetched = datasource
+            .get_as_df_between_until(query.clone(), 1030, 1110, &StopSignal::new())
+            .await
+            .unwrap();
+        assert!(fetched.is_complete());
+        assert_eq!(fetched.data.height(), 7);
+        assert_eq!(fetched.next_block, 10);
+        let fetched = datasource
+            .get_as_df_between_until(query.clone(), 1001, 1011, &StopSignal::new())
+            .await
+            .unwrap();
+        assert!(fetched.is_complete());
+        assert_eq!(fetched.data.height(), 0);
+
+        let stop = StopSignal::new();
+        stop.cancel();
+        let error = datasource
+            .get_as_df_between_until(query, 1030, 1110, &stop)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            DatasourceError::TimeRangeUnresolved {
+                reason: StopReason::Cancelled
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_height_lookup_is_retried() {
+        let blocks = (0..10u64)
+            .map(|number| json!({"header": {"number": number, "timestamp": 1000 + 12 * number}}))
+            .collect();
+        let server = MockArchive::new(blocks)
+            .with_failure(Endpoint::Height, Failure::Status(503, "busy".to_string()))
+            .start()
+            .await
+            .unwrap();
+        let config = DatasourceConfig::new(server.url(), 2).with_retry_policy(RetryPolicy {
+            base_delay: Duration::ZERO,
+            ..Default::default()
+        });
+        let datasource = Datasource::new(config);
+
+        assert_eq!(datasource.get_block_at_time(1030).await.unwrap(), Some(3));
+        assert_eq!(server.stats().height_requests, 2);
+    }
+}
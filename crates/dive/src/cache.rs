@@ -0,0 +1,476 @@
+//! On-disk cache of worker responses.
+//!
+//! Each entry holds one worker response, gzip-compressed, under a key derived from the archive
+//! URL, the canonical query JSON and `fromBlock`. The cache keeps its total size under a cap by
+//! evicting the least recently used entries. `Datasource` only stores batches that end below the
+//! dataset height, so cached data never goes stale.
+
+use crate::utils::canonical_json;
+use anyhow::{Context, Error};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const EXTENSION: &str = "json.gz";
+/// The extension of entries being written. Any left at `open` are from interrupted writes.
+const TMP_EXTENSION: &str = "tmp";
+
+/// A cached worker response.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedBatch {
+    last_block: u64,
+    blocks: Vec<Value>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    size: u64,
+    last_used: u64,
+    /// The clock when the entry was stored, which tells a replaced entry from the one read.
+    stored: u64,
+}
+
+/// Sizes and recency of the cached entries. `clock` increases on every access.
+#[derive(Debug, Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    total_bytes: u64,
+    clock: u64,
+}
+
+impl Index {
+    /// Marks `key` as just used and returns when its entry was stored, if it is present.
+    fn touch(&mut self, key: &str) -> Option<u64> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.clock;
+        Some(entry.stored)
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.total_bytes -= entry.size;
+        Some(entry)
+    }
+
+    fn least_recently_used(&self) -> Option<String> {
+        self.entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+/// A size-capped, least-recently-used cache of worker responses stored in a directory.
+///
+/// The cache is best effort: entries that cannot be read or written are treated as misses.
+///
+/// # Examples
+///
+/// no_run
+/// let cache = Arc::new(ResponseCache::open("~/.cache/dive", 512 * 1024 * 1024)?);
+/// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10).with_cache(cache);
+///
+#[derive(Debug)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Mutex<Index>,
+    /// Numbers temporary files, so concurrent inserts of one key never share one.
+    next_tmp: AtomicU64,
+}
+
+impl ResponseCache {
+    /// Opens the cache in `dir`, creating the directory if needed, and picks up entries left by
+    /// earlier runs. Entries beyond `max_bytes` are evicted, oldest first, and temporary files of
+    /// interrupted writes are deleted.
+    pub fn open(dir: impl AsRef<Path>, max_bytes: u64) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).with_context(|| format!("creating cache {}", dir.display()))?;
+
+        let mut found = Vec::new();
+        for entry in
+            fs::read_dir(&dir).with_context(|| format!("reading cache {}", dir.display()))?
+        {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            if Path::new(&file_name).extension() == Some(TMP_EXTENSION.as_ref()) {
+                let _ = fs::remove_file(entry.path());
+                continue;
+            }
+            let Some(key) = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(&format!(".{}", EXTENSION)))
+            else {
+                continue;
+            };
+            let metadata = entry.metadata()?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            found.push((modified, key.to_string(), metadata.len()));
+        }
+        found.sort();
+
+        let mut index = Index::default();
+        for (_, key, size) in found {
+            index.clock += 1;
+            index.total_bytes += size;
+            index.entries.insert(
+                key,
+                Entry {
+                    size,
+                    last_used: index.clock,
+                    stored: index.clock,
+                },
+            );
+        }
+
+        let cache = Self {
+            dir,
+            max_bytes,
+            index: Mutex::new(index),
+            next_tmp: AtomicU64::new(0),
+        };
+        cache.evict(&mut cache.index.lock().unwrap());
+        Ok(cache)
+    }
+
+    /// Returns the key of the worker response for `query` starting at `from_block` on the archive
+    /// at `base_url`. Queries that differ only in key order share a key.
+    pub fn key(base_url: &str, query: &Value, from_block: u64) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(base_url.as_bytes());
+        hasher.update([0]);
+        hasher.update(canonical_json(query).as_bytes());
+        hasher.update([0]);
+        hasher.update(from_block.to_le_bytes());
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Returns the cached blocks and last scanned block for `key`, if present.
+    ///
+    /// The entry is read and decompressed on the blocking thread pool, without holding the index
+    /// lock, so concurrent lookups do not wait on each other. An entry that cannot be read is
+    /// removed, unless an insert has replaced it in the meantime.
+    pub async fn get(&self, key: &str) -> Option<(Vec<Value>, u64)> {
+        let stored = self.index.lock().unwrap().touch(key)?;
+
+        let path = self.path(key);
+        let read = tokio::task::spawn_blocking({
+            let path = path.clone();
+            move || {
+                let batch = Self::read(&path)?;
+                // Keep the on-disk recency in step for the next `open`.
+                if let Ok(file) = File::options().write(true).open(&path) {
+                    let _ = file.set_modified(SystemTime::now());
+                }
+                Ok::<_, Error>(batch)
+            }
+        });
+        match read.await {
+            Ok(Ok(batch)) => Some((batch.blocks, batch.last_block)),
+            _ => {
+                let mut index = self.index.lock().unwrap();
+                if index
+                    .entries
+                    .get(key)
+                    .is_some_and(|entry| entry.stored == stored)
+                {
+                    index.remove(key);
+                    let _ = fs::remove_file(&path);
+                }
+                None
+            }
+        }
+    }
+
+    /// Stores a worker response under `key`, evicting least recently used entries to stay under
+    /// the size cap.
+    ///
+    /// The entry is compressed into a temporary file before the index is locked. It is renamed
+    /// into place with the index locked, so the files on disk and the index always list the same
+    /// entries. This blocks on compression and disk I/O, so async callers should run it on the
+    /// blocking thread pool.
+    pub fn insert(&self, key: &str, blocks: &[Value], last_block: u64) {
+        let path = self.path(key);
+        let tmp = path.with_extension(format!(
+            "{}.{}",
+            self.next_tmp.fetch_add(1, Ordering::Relaxed),
+            TMP_EXTENSION
+        ));
+        let Ok(size) = Self::write(&tmp, blocks, last_block) else {
+            let _ = fs::remove_file(&tmp);
+            return;
+        };
+
+        let mut index = self.index.lock().unwrap();
+        if fs::rename(&tmp, &path).is_err() {
+            let _ = fs::remove_file(&tmp);
+            return;
+        }
+        index.remove(key);
+        index.clock += 1;
+        index.total_bytes += size;
+        let clock = index.clock;
+        index.entries.insert(
+            key.to_string(),
+            Entry {
+                size,
+                last_used: clock,
+                stored: clock,
+            },
+        );
+        self.evict(&mut index);
+    }
+
+    /// Returns the number of cached responses.
+    pub fn len(&self) -> usize {
+        self.index.lock().unwrap().entries.len()
+    }
+
+    /// Returns `true` if nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the total compressed size of the cached responses in bytes.
+    pub fn size_bytes(&self) -> u64 {
+        self.index.lock().unwrap().total_bytes
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, EXTENSION))
+    }
+
+    fn evict(&self, index: &mut Index) {
+        while index.total_bytes > self.max_bytes {
+            let Some(key) = index.least_recently_used() else {
+                break;
+            };
+            index.remove(&key);
+            let _ = fs::remove_file(self.path(&key));
+        }
+    }
+
+    fn read(path: &Path) -> Result<CachedBatch, Error> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(GzDecoder::new(
+            file,
+        )))?)
+    }
+
+    /// Writes the compressed entry to `path` and returns its size.
+    fn write(path: &Path, blocks: &[Value], last_block: u64) -> Result<u64, Error> {
+        #[derive(Serialize)]
+        struct CachedBatchRef<'a> {
+            last_block: u64,
+            blocks: &'a [Value],
+        }
+
+        let mut encoder =
+            GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+        serde_json::to_writer(&mut encoder, &CachedBatchRef { last_block, blocks })?;
+        encoder.finish()?.flush()?;
+        Ok(fs::metadata(path)?.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasource::{Datasource, DatasourceConfig};
+    use crate::mock_archive::MockArchive;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/blocks.json");
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dive-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn usdc_query() -> Value {
+        json!({
+            "logs": [{"address": ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"]}],
+            "fields": {"log": {"address": true, "topics": true, "data": true}},
+        })
+    }
+
+    #[test]
+    fn test_key_ignores_query_key_order() {
+        let a = json!({"logs": [{"address": ["0x1"]}], "fields": {"log": {"data": true}}});
+        let b = json!({"fields": {"log": {"data": true}}, "logs": [{"address": ["0x1"]}]});
+
+        assert_eq!(
+            ResponseCache::key("http://a", &a, 5),
+            ResponseCache::key("http://a", &b, 5)
+        );
+        assert_ne!(
+            ResponseCache::key("http://a", &a, 5),
+            ResponseCache::key("http://a", &a, 6)
+        );
+        assert_ne!(
+            ResponseCache::key("http://a", &a, 5),
+            ResponseCache::key("http://b", &a, 5)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repeated_fetch_is_served_from_disk() {
+        let dir = cache_dir("hit");
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_worker_range_size(10)
+            .with_max_blocks_per_response(4)
+            .start()
+            .await
+            .unwrap();
+        let cache = Arc::new(ResponseCache::open(&dir, 1 << 20).unwrap());
+        let datasource =
+            Datasource::new(DatasourceConfig::new(server.url(), 4).with_cache(cache.clone()));
+
+        let first = datasource
+            .get_as_df(usdc_query(), 14000000, 14000019)
+            .await
+            .unwrap();
+        let queries = server.stats().query_requests;
+        assert!(!cache.is_empty());
+
+        let second = datasource
+            .get_as_df(usdc_query(), 14000000, 14000019)
+            .await
+            .unwrap();
+        assert!(first.equals(&second));
+        assert_eq!(server.stats().query_requests, queries);
+
+        // A fresh process picks up the same entries.
+        let reopened = ResponseCache::open(&dir, 1 << 20).unwrap();
+        assert_eq!(reopened.len(), cache.len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batches_at_the_head_are_not_cached() {
+        let dir = cache_dir("head");
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_worker_range_size(10)
+            .with_max_blocks_per_response(4)
+            .start()
+            .await
+            .unwrap();
+        let cache = Arc::new(ResponseCache::open(&dir, 1 << 20).unwrap());
+        let datasource =
+            Datasource::new(DatasourceConfig::new(server.url(), 4).with_cache(cache.clone()));
+
+        datasource
+            .get_data_in_range(usdc_query(), 14000020, 14000029)
+            .await
+            .unwrap();
+        let queries = server.stats().query_requests;
+        datasource
+            .get_data_in_range(usdc_query(), 14000020, 14000029)
+            .await
+            .unwrap();
+
+        // The batch ending at the dataset height is fetched again, the others are not.
+        assert_eq!(server.stats().query_requests, queries + 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_entries_are_evicted() {
+        let dir = cache_dir("evict");
+        let blocks: Vec<Value> = (0..50)
+            .map(|n| json!({"header": {"number": n, "hash": format!("0x{:064x}", n * 7919)}}))
+            .collect();
+        let probe = ResponseCache::open(&dir, u64::MAX).unwrap();
+        probe.insert("probe", &blocks, 49);
+        let entry_size = probe.size_bytes();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let cache = ResponseCache::open(&dir, entry_size * 2).unwrap();
+        cache.insert("a", &blocks, 49);
+        cache.insert("b", &blocks, 49);
+        assert!(cache.get("a").await.is_some());
+        cache.insert("c", &blocks, 49);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.size_bytes() <= entry_size * 2);
+        assert!(cache.get("b").await.is_none());
+        assert_eq!(cache.get("a").await.unwrap().1, 49);
+        assert!(cache.get("c").await.is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_removes_interrupted_writes() {
+        let dir = cache_dir("tmp");
+        let cache = ResponseCache::open(&dir, u64::MAX).unwrap();
+        cache.insert("a", &[json!({"header": {"number": 1}})], 1);
+        let leftover = dir.join(format!("b.json.3.{}", TMP_EXTENSION));
+        fs::write(&leftover, b"partial").unwrap();
+
+        let reopened = ResponseCache::open(&dir, u64::MAX).unwrap();
+        assert_eq!(reopened.len(), 1);
+        assert!(!leftover.exists());
+        assert!(cache.path("a").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_inserts_keep_index_and_files_in_step() {
+        let dir = cache_dir("concurrent");
+        let blocks: Vec<Value> = (0..20)
+            .map(|n| json!({"header": {"number": n, "hash": format!("0x{:064x}", n * 7919)}}))
+            .collect();
+        let probe = ResponseCache::open(&dir, u64::MAX).unwrap();
+        probe.insert("probe", &blocks, 19);
+        let entry_size = probe.size_bytes();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let cache = ResponseCache::open(&dir, entry_size * 3).unwrap();
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let (cache, blocks) = (&cache, &blocks);
+                scope.spawn(move || {
+                    for i in 0..20 {
+                        cache.insert(&format!("{}", (thread * 7 + i) % 10), blocks, 19);
+                    }
+                });
+            }
+        });
+
+        let mut on_disk: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        on_disk.sort();
+        let index = cache.index.lock().unwrap();
+        let mut indexed: Vec<String> = index
+            .entries
+            .keys()
+            .map(|key| format!("{}.{}", key, EXTENSION))
+            .collect();
+        indexed.sort();
+        assert_eq!(on_disk, indexed);
+        assert!(index.total_bytes <= entry_size * 3);
+        drop(index);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
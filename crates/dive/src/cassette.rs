@@ -0,0 +1,353 @@
+//! Recording and replaying archive traffic.
+//!
+//! `RecordingTransport` wraps another transport and appends every router and worker exchange to a
+//! cassette file, one JSON object per line. `ReplayTransport` serves a cassette back, so a real
+//! backfill can be rerun offline as a reproducible fixture.
+
+use crate::error::{BlockRange, DatasourceError};
+use crate::transport::{self, ArchiveTransport};
+use anyhow::{Context, Error};
+use futures::future::BoxFuture;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The outcome of a recorded call: a status code and the response body.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// One recorded exchange with the archive.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Interaction {
+    Height {
+        response: RecordedResponse,
+    },
+    Worker {
+        block: u64,
+        response: RecordedResponse,
+    },
+    Query {
+        worker_url: String,
+        query: Value,
+        response: RecordedResponse,
+    },
+}
+
+impl Interaction {
+    fn response(&self) -> &RecordedResponse {
+        match self {
+            Self::Height { response }
+            | Self::Worker { response, .. }
+            | Self::Query { response, .. } => response,
+        }
+    }
+}
+
+/// A transport that forwards calls to another transport and records every exchange.
+///
+/// Responses are recorded whether they succeeded or failed with an HTTP status; failures without
+/// a response, such as refused connections, are not recorded.
+///
+/// # Examples
+///
+/// no_run
+/// let recorder = RecordingTransport::create("backfill.cassette", Arc::new(ReqwestTransport::default()))?;
+/// let datasource = Datasource::with_transport(config, Arc::new(recorder));
+///
+pub struct RecordingTransport {
+    inner: Arc<dyn ArchiveTransport>,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl RecordingTransport {
+    /// Creates the cassette at `path`, replacing any existing file.
+    pub fn create(path: impl AsRef<Path>, inner: Arc<dyn ArchiveTransport>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file =
+            File::create(path).with_context(|| format!("creating cassette {}", path.display()))?;
+        Ok(Self {
+            inner,
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Appends an exchange to the cassette and passes the result through.
+    fn record(
+        &self,
+        result: Result<String, DatasourceError>,
+        interaction: impl FnOnce(RecordedResponse) -> Interaction,
+    ) -> Result<String, DatasourceError> {
+        let response = match &result {
+            Ok(body) => RecordedResponse {
+                status: StatusCode::OK.as_u16(),
+                body: body.clone(),
+            },
+            Err(DatasourceError::Http { status, body, .. }) => RecordedResponse {
+                status: status.as_u16(),
+                body: body.clone(),
+            },
+            Err(_) => return result,
+        };
+
+        let line = serde_json::to_string(&interaction(response)).expect("interactions serialize");
+        let mut writer = self.writer.lock().unwrap();
+        // A cassette that cannot be written should not fail the fetch itself.
+        let _ = writeln!(writer, "{}", line).and_then(|_| writer.flush());
+        result
+    }
+}
+
+impl ArchiveTransport for RecordingTransport {
+    fn height<'a>(&'a self, base_url: &'a str) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        Box::pin(async move {
+            let result = self.inner.height(base_url).await;
+            self.record(result, |response| Interaction::Height { response })
+        })
+    }
+
+    fn worker<'a>(
+        &'a self,
+        base_url: &'a str,
+        block: u64,
+    ) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        Box::pin(async move {
+            let result = self.inner.worker(base_url, block).await;
+            self.record(result, |response| Interaction::Worker { block, response })
+        })
+    }
+
+    fn query<'a>(
+        &'a self,
+        worker_url: &'a str,
+        query: &'a Value,
+    ) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        Box::pin(async move {
+            let result = self.inner.query(worker_url, query).await;
+            self.record(result, |response| Interaction::Query {
+                worker_url: worker_url.to_string(),
+                query: query.clone(),
+                response,
+            })
+        })
+    }
+}
+
+/// A transport that serves the exchanges of a recorded cassette.
+///
+/// Worker lookups are matched by block and worker queries by their JSON body, so concurrent
+/// fetches replay correctly regardless of order. Exchanges with the same key are served in the
+/// order they were recorded, which replays retried failures too. Height calls are served in order
+/// and the last one repeats. A call with no matching exchange fails with `404 Not Found`.
+///
+/// # Examples
+///
+/// no_run
+/// let player = ReplayTransport::open("backfill.cassette")?;
+/// let datasource = Datasource::with_transport(config, Arc::new(player));
+///
+pub struct ReplayTransport {
+    interactions: Mutex<Vec<Option<Interaction>>>,
+    last_height: Mutex<Option<RecordedResponse>>,
+}
+
+impl ReplayTransport {
+    /// Loads the cassette at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("opening cassette {}", path.display()))?;
+        let mut interactions = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let interaction = serde_json::from_str(&line)
+                .with_context(|| format!("parsing {} line {}", path.display(), number + 1))?;
+            interactions.push(interaction);
+        }
+        Ok(Self::new(interactions))
+    }
+
+    /// Creates a player serving the given exchanges.
+    pub fn new(interactions: Vec<Interaction>) -> Self {
+        Self {
+            interactions: Mutex::new(interactions.into_iter().map(Some).collect()),
+            last_height: Mutex::new(None),
+        }
+    }
+
+    /// Returns how many recorded exchanges have not been served yet.
+    pub fn remaining(&self) -> usize {
+        self.interactions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|interaction| interaction.is_some())
+            .count()
+    }
+
+    /// Removes and returns the first unserved exchange accepted by `matches`.
+    fn take(&self, matches: impl Fn(&Interaction) -> bool) -> Option<Interaction> {
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions
+            .iter_mut()
+            .find(|slot| slot.as_ref().is_some_and(&matches))
+            .and_then(Option::take)
+    }
+
+    fn replay(
+        response: Option<RecordedResponse>,
+        url: String,
+        range: Option<BlockRange>,
+    ) -> Result<String, DatasourceError> {
+        let Some(response) = response else {
+            return Err(DatasourceError::Http {
+                status: StatusCode::NOT_FOUND,
+                body: "no recorded interaction".to_string(),
+                url,
+                range,
+            });
+        };
+        let status = StatusCode::from_u16(response.status).unwrap_or(StatusCode::OK);
+        if status.is_success() {
+            Ok(response.body)
+        } else {
+            Err(DatasourceError::Http {
+                status,
+                body: response.body,
+                url,
+                range,
+            })
+        }
+    }
+}
+
+impl ArchiveTransport for ReplayTransport {
+    fn height<'a>(&'a self, base_url: &'a str) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        let mut last_height = self.last_height.lock().unwrap();
+        let recorded = self
+            .take(|i| matches!(i, Interaction::Height { .. }))
+            .map(|i| i.response().clone());
+        if recorded.is_some() {
+            last_height.clone_from(&recorded);
+        }
+        let response = recorded.or_else(|| last_height.clone());
+        let result = Self::replay(response, transport::height_url(base_url), None);
+        Box::pin(async move { result })
+    }
+
+    fn worker<'a>(
+        &'a self,
+        base_url: &'a str,
+        block: u64,
+    ) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        let response = self
+            .take(|i| matches!(i, Interaction::Worker { block: b, .. } if *b == block))
+            .map(|i| i.response().clone());
+        let result = Self::replay(
+            response,
+            transport::worker_lookup_url(base_url, block),
+            Some(BlockRange::starting_at(block)),
+        );
+        Box::pin(async move { result })
+    }
+
+    fn query<'a>(
+        &'a self,
+        worker_url: &'a str,
+        query: &'a Value,
+    ) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        let response = self
+            .take(|i| matches!(i, Interaction::Query { query: q, .. } if q == query))
+            .map(|i| i.response().clone());
+        let result = Self::replay(
+            response,
+            worker_url.to_string(),
+            BlockRange::of_query(query),
+        );
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasource::{Datasource, DatasourceConfig};
+    use crate::mock_archive::{Endpoint, Failure, MockArchive};
+    use crate::retry::RetryPolicy;
+    use crate::transport::ReqwestTransport;
+    use serde_json::json;
+    use std::time::Duration;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/blocks.json");
+
+    fn config(base_url: String) -> DatasourceConfig {
+        DatasourceConfig::new(base_url, 10).with_retry_policy(RetryPolicy {
+            base_delay: Duration::ZERO,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay_offline() {
+        let path = std::env::temp_dir().join(format!("dive-cassette-{}.jsonl", std::process::id()));
+        let query = json!({
+            "logs": [{"address": ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"]}],
+            "fields": {"log": {"address": true, "topics": true, "data": true}},
+        });
+
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_worker_range_size(10)
+            .with_max_blocks_per_response(4)
+            .with_failure(Endpoint::Query, Failure::Status(503, "busy".to_string()))
+            .start()
+            .await
+            .unwrap();
+        let base_url = server.url();
+        let recorder =
+            RecordingTransport::create(&path, Arc::new(ReqwestTransport::default())).unwrap();
+        let recorded = Datasource::with_transport(config(base_url.clone()), Arc::new(recorder))
+            .get_as_df(query.clone(), 14000000, 14000029)
+            .await
+            .unwrap();
+        drop(server);
+
+        let player = Arc::new(ReplayTransport::open(&path).unwrap());
+        let replayed = Datasource::with_transport(config(base_url), player.clone())
+            .get_as_df(query, 14000000, 14000029)
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(recorded.equals(&replayed));
+        assert_eq!(player.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_replay_without_recording_fails() {
+        let player = ReplayTransport::new(vec![Interaction::Height {
+            response: RecordedResponse {
+                status: 200,
+                body: "42".to_string(),
+            },
+        }]);
+        assert_eq!(player.height("http://router").await.unwrap(), "42");
+        assert_eq!(player.height("http://router").await.unwrap(), "42");
+
+        let error = player
+            .query("http://worker", &json!({"fromBlock": 1}))
+            .await
+            .unwrap_err();
+        assert!(!error.is_retryable());
+    }
+}
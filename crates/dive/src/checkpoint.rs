@@ -0,0 +1,76 @@
+//! Checkpoints for resuming long range fetches.
+//!
+//! A checkpoint records the hash of a query and the last block whose data has been handed to the
+//! caller. `Datasource::stream_range_resumable` writes one after each batch and continues from it
+//! on the next run.
+
+use crate::error::DatasourceError;
+use crate::utils::query_hash;
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Progress of a range fetch: the query it belongs to and the last block persisted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub query_hash: String,
+    pub last_block: u64,
+}
+
+impl Checkpoint {
+    /// Creates a checkpoint for `query` up to and including `last_block`.
+    pub fn new(query: &Value, last_block: u64) -> Self {
+        Self {
+            query_hash: query_hash(query),
+            last_block,
+        }
+    }
+
+    /// The block a resumed fetch starts from.
+    pub fn next_block(&self) -> u64 {
+        self.last_block + 1
+    }
+
+    /// Returns `true` if the checkpoint was written for `query`.
+    pub fn matches(&self, query: &Value) -> bool {
+        self.query_hash == query_hash(query)
+    }
+
+    /// Reads the checkpoint at `path`, or `None` if there is no file yet.
+    pub fn load(path: &Path) -> Result<Option<Self>, DatasourceError> {
+        let checkpoint_error = |source: Error| DatasourceError::Checkpoint {
+            path: path.to_path_buf(),
+            source,
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(checkpoint_error(e.into())),
+        };
+        serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| checkpoint_error(e.into()))
+    }
+
+    /// Writes the checkpoint to `path` through a temporary file next to it, which is synced to
+    /// disk before it replaces `path`, so a crash never leaves a truncated checkpoint behind.
+    pub fn save(&self, path: &Path) -> Result<(), DatasourceError> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let contents = serde_json::to_string(self).expect("checkpoints serialize");
+        File::create(&tmp)
+            .and_then(|mut file| {
+                file.write_all(contents.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| DatasourceError::Checkpoint {
+                path: path.to_path_buf(),
+                source: e.into(),
+            })
+    }
+}
@@ -1,29 +1,64 @@
+use crate::block_time::BlockTimeIndex;
+use crate::cache::ResponseCache;
+use crate::checkpoint::Checkpoint;
+use crate::error::{BlockRange, DatasourceError};
+use crate::http::HttpConfig;
+use crate::network::NetworkInfo;
+use crate::planner::{ChunkPlanner, ChunkSample, ChunkSizing};
+use crate::progress::{ProgressObserver, ProgressTracker};
+use crate::rate_limit::{DirectRateLimiter, RateLimit};
+use crate::retry::RetryPolicy;
+use crate::stop::{PartialRange, StopSignal};
+use crate::telemetry::{self, Endpoint};
+use crate::transport::{self, ArchiveTransport, ReqwestTransport};
 use crate::utils;
-use anyhow::Error;
-use governor::{
-    clock::DefaultClock,
-    middleware::NoOpMiddleware,
-    state::{direct::NotKeyed, InMemoryState},
-    RateLimiter,
-};
+use crate::workers::{WorkerPool, WorkerRange, WorkerStats};
+use futures::future;
+use futures::stream::{self, FuturesUnordered, Stream, StreamExt, TryStreamExt};
 use polars::prelude::*;
-use rayon::prelude::*;
-use reqwest::Client;
-use serde_json::Value;
+use reqwest::{Client, StatusCode};
+use serde::de::Error as _;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{futures, Semaphore};
+use std::time::{Duration, Instant};
+use to_df::fields::Dataset;
+use tokio::sync::Semaphore;
 use tokio::task;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, instrument, warn, Span};
 
-use utils::add_from_block;
+use utils::{add_from_block, add_to_block};
 /// Configuration for the `Datasource` which includes base URL, maximum concurrent requests,
-/// rate limiter, and semaphore for limiting concurrent operations.
+/// rate limiters, semaphore for limiting concurrent operations, the retry policy, how often
+/// `Datasource::follow` polls the dataset height, how long a failing worker is avoided, an
+/// optional on-disk response cache, the HTTP client settings and how parallel chunks are sized
+/// and aligned.
+///
+/// `rate_limiter` applies to every request. `router_rate_limiter` additionally applies to the
+/// `/height` and `/worker` router calls and `worker_rate_limiter` to the worker queries.
 #[derive(Clone, Debug)]
 pub struct DatasourceConfig {
     pub base_url: String,
     pub max_concurrent_requests: usize,
-    pub rate_limiter:
-        Option<Arc<RateLimiter<NotKeyed, InMemoryState, DefaultClock, NoOpMiddleware>>>,
+    pub rate_limiter: Option<Arc<DirectRateLimiter>>,
+    pub router_rate_limiter: Option<Arc<DirectRateLimiter>>,
+    pub worker_rate_limiter: Option<Arc<DirectRateLimiter>>,
     pub semaphore: Option<Arc<Semaphore>>,
+    pub retry_policy: RetryPolicy,
+    pub follow_poll_interval: Duration,
+    pub worker_cooldown: Duration,
+    pub cache: Option<Arc<ResponseCache>>,
+    pub http: HttpConfig,
+    /// A preconfigured client to use instead of building one from `http`.
+    pub client: Option<Client>,
+    /// Adapts the size of parallel chunks to the data when set; otherwise they are fixed.
+    pub chunk_sizing: Option<ChunkSizing>,
+    /// Plans parallel chunks along the block ranges of archive workers.
+    pub align_chunks_to_workers: bool,
 }
 
 impl DatasourceConfig {
@@ -36,195 +71,1598 @@ impl DatasourceConfig {
     ///
     pub fn new(base_url: String, max_concurrent_requests: usize) -> Self {
         let semaphore = Arc::new(Semaphore::new(max_concurrent_requests));
-        let rate_limiter = None; // Configure as needed
         Self {
             base_url,
             max_concurrent_requests,
-            rate_limiter,
+            rate_limiter: None,
+            router_rate_limiter: None,
+            worker_rate_limiter: None,
             semaphore: Some(semaphore),
+            retry_policy: RetryPolicy::default(),
+            follow_poll_interval: Duration::from_secs(5),
+            worker_cooldown: Duration::from_secs(30),
+            cache: None,
+            http: HttpConfig::default(),
+            client: None,
+            chunk_sizing: None,
+            align_chunks_to_workers: false,
         }
     }
+
+    /// Creates a `DatasourceConfig` for a known network, with up to 10 concurrent requests.
+    /// Accepts a built-in `Network` or a `NetworkInfo` from a `NetworkRegistry`.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::for_network(Network::Base);
+    ///
+    pub fn for_network(network: impl Into<NetworkInfo>) -> Self {
+        Self::new(network.into().archive_url, 10)
+    }
+
+    /// Sets the policy used to retry failed router and worker requests.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_retry_policy(RetryPolicy { max_attempts: 3, ..Default::default() });
+    ///
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sets how often `Datasource::follow` polls the dataset height once it has caught up.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_follow_poll_interval(Duration::from_secs(2));
+    ///
+    pub fn with_follow_poll_interval(mut self, interval: Duration) -> Self {
+        self.follow_poll_interval = interval;
+        self
+    }
+
+    /// Sets how long a worker that failed a request is avoided before it is used again.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_worker_cooldown(Duration::from_secs(60));
+    ///
+    pub fn with_worker_cooldown(mut self, cooldown: Duration) -> Self {
+        self.worker_cooldown = cooldown;
+        self
+    }
+
+    /// Limits the rate of all requests, router calls and worker queries combined.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_rate_limit(RateLimit::per_second(10).with_burst(20));
+    ///
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limiter = Some(limit.build());
+        self
+    }
+
+    /// Limits the rate of `/height` and `/worker` calls to the router.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_router_rate_limit(RateLimit::per_minute(300));
+    ///
+    pub fn with_router_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.router_rate_limiter = Some(limit.build());
+        self
+    }
+
+    /// Limits the rate of query POSTs to workers.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_worker_rate_limit(RateLimit::per_second(5));
+    ///
+    pub fn with_worker_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.worker_rate_limiter = Some(limit.build());
+        self
+    }
+
+    /// Sets the timeouts, proxy, headers and connection pool of the HTTP client `Datasource::new`
+    /// builds.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_http(HttpConfig::default().with_request_timeout(Duration::from_secs(60)));
+    ///
+    pub fn with_http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Uses `client` for all archive calls. The `http` settings are then ignored.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let client = reqwest::Client::builder().https_only(true).build()?;
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_client(client);
+    ///
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Serves repeated worker queries from `cache`. Only batches that end below the dataset height
+    /// are stored, so responses near the chain head are always fetched fresh.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let cache = Arc::new(ResponseCache::open("/var/cache/dive", 1 << 30)?);
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_cache(cache);
+    ///
+    pub fn with_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Sizes the chunks of `Datasource::get_parallelel_chunks` adaptively. The `chunk_size`
+    /// passed there becomes the first guess; later chunks are sized from the bytes and items per
+    /// block of the chunks fetched so far.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_chunk_sizing(ChunkSizing::default().with_target_bytes(4 << 20));
+    ///
+    pub fn with_chunk_sizing(mut self, sizing: ChunkSizing) -> Self {
+        self.chunk_sizing = Some(sizing);
+        self
+    }
+
+    /// Makes `Datasource::get_parallelel_chunks` discover the block ranges of the archive workers
+    /// first and never let a chunk span two of them, so no request is cut short at the end of a
+    /// worker's range. Discovery costs one router call and a few small worker queries per worker;
+    /// discovered ranges are kept for later fetches.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_worker_aligned_chunks(true);
+    ///
+    pub fn with_worker_aligned_chunks(mut self, align: bool) -> Self {
+        self.align_chunks_to_workers = align;
+        self
+    }
+}
+
+/// How many times the router is asked for another worker when it assigns one in cooldown.
+const ROUTER_FAILOVER_ATTEMPTS: usize = 3;
+
+/// The kinds of archive calls, each of which can have its own rate limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RequestKind {
+    Router,
+    Worker,
 }
 
-/// Datasource struct to interact with the API, perform rate-limited requests,
-/// and fetch data as JSON or Polars DataFrame.
-pub struct Datasource {
-    client: Client,
-    config: DatasourceConfig,
-}
+/// Datasource struct to interact with the API, perform rate-limited requests,
+/// and fetch data as JSON or Polars DataFrame.
+pub struct Datasource {
+    transport: Arc<dyn ArchiveTransport>,
+    config: DatasourceConfig,
+    /// The highest dataset height seen so far, or 0 before the first height call.
+    known_height: AtomicU64,
+    workers: WorkerPool,
+    progress_observer: Option<Arc<dyn ProgressObserver>>,
+    /// Timestamps of the block headers fetched to resolve times to blocks.
+    block_times: BlockTimeIndex,
+}
+
+impl Datasource {
+    /// Creates a new `Datasource` with the specified configuration.
+    ///
+    /// This is a convenience for the default HTTP settings. If the HTTP client cannot be built
+    /// from `config.http`, a warning is logged and a client with default settings is used
+    /// instead, so headers, proxy and timeouts from `config.http` are not applied; use
+    /// `Datasource::try_new` to handle that as an error.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10);
+    /// let datasource = Datasource::new(config);
+    ///
+    pub fn new(config: DatasourceConfig) -> Self {
+        let client = match &config.client {
+            Some(client) => client.clone(),
+            None => config.http.build_client().unwrap_or_else(|error| {
+                warn!(%error, "invalid HTTP client configuration, using the default client");
+                Client::new()
+            }),
+        };
+        let transport = Arc::new(ReqwestTransport::new(client));
+        Self::with_transport(config, transport)
+    }
+
+    /// Creates a new `Datasource`, using `config.client` if set and otherwise building a client
+    /// from `config.http`.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+    ///     .with_http(HttpConfig::default().with_proxy("http://proxy.internal:3128"));
+    /// let datasource = Datasource::try_new(config)?;
+    ///
+    pub fn try_new(config: DatasourceConfig) -> Result<Self, DatasourceError> {
+        let client = match &config.client {
+            Some(client) => client.clone(),
+            None => config
+                .http
+                .build_client()
+                .map_err(|source| DatasourceError::ClientConfig { source })?,
+        };
+        let transport = Arc::new(ReqwestTransport::new(client));
+        Ok(Self::with_transport(config, transport))
+    }
+
+    /// Creates a new `Datasource` that makes its archive calls through `transport`, for example
+    /// a `MockTransport` in tests.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let transport = Arc::new(MockTransport::new());
+    /// let datasource = Datasource::with_transport(config, transport.clone());
+    ///
+    pub fn with_transport(config: DatasourceConfig, transport: Arc<dyn ArchiveTransport>) -> Self {
+        let workers = WorkerPool::new(config.worker_cooldown);
+        Self {
+            transport,
+            config,
+            known_height: AtomicU64::new(0),
+            workers,
+            progress_observer: None,
+            block_times: BlockTimeIndex::new(),
+        }
+    }
+
+    /// Reports the progress of range fetches to `observer`. `get_data_in_range`, `get_as_df`,
+    /// `get_parallelel_chunks` and the streaming methods other than `follow` report progress.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let datasource = Datasource::new(config).with_progress_observer(Arc::new(progress_bar));
+    ///
+    pub fn with_progress_observer(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.progress_observer = Some(observer);
+        self
+    }
+
+    /// Creates a tracker reporting the progress of a fetch of `start_block..=end_block`.
+    fn progress_tracker(&self, start_block: u64, end_block: u64) -> ProgressTracker {
+        ProgressTracker::new(self.progress_observer.clone(), start_block, end_block)
+    }
+
+    /// Retrieves the current dataset height from the API.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let height = datasource.get_dataset_height().await?;
+    ///
+    #[instrument(level = "debug", skip(self))]
+    pub async fn get_dataset_height(&self) -> Result<u64, DatasourceError> {
+        self.check_rate_limit(RequestKind::Router).await;
+
+        let base_url = &self.config.base_url;
+        let result = self.transport.height(base_url).await;
+        telemetry::record_request(Endpoint::Height, &result);
+        let body = result?;
+        let decode_error = |source| DatasourceError::Decode {
+            url: transport::height_url(base_url),
+            range: None,
+            source,
+        };
+        let response: Value = serde_json::from_str(&body).map_err(decode_error)?;
+
+        let height = response
+            .as_u64()
+            .ok_or_else(|| DatasourceError::ArchiveError {
+                payload: response.clone(),
+                url: transport::height_url(base_url),
+                range: None,
+            })?;
+        self.known_height.fetch_max(height, Ordering::Relaxed);
+        debug!(height, "dataset height");
+        Ok(height)
+    }
+
+    /// Retrieves the worker URL for a specific block number.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let worker_url = datasource.get_worker_url(12345).await?;
+    ///
+    #[instrument(level = "debug", skip(self))]
+    pub async fn get_worker_url(&self, block_number: u64) -> Result<String, DatasourceError> {
+        self.check_rate_limit(RequestKind::Router).await;
+
+        let result = self
+            .transport
+            .worker(&self.config.base_url, block_number)
+            .await;
+        telemetry::record_request(Endpoint::Worker, &result);
+        match result {
+            Ok(worker_url) => {
+                let worker_url = worker_url.trim().to_string();
+                debug!(%worker_url, "worker assigned");
+                Ok(worker_url)
+            }
+            Err(DatasourceError::Http {
+                status, body, url, ..
+            }) => Err(DatasourceError::WorkerUnavailable {
+                block: block_number,
+                url,
+                status,
+                body,
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fetches data from the specified block using the worker URL and query.
+    ///
+    /// If the query sets `toBlock`, no blocks past it are returned and the last block is at most
+    /// `toBlock`. An empty response is valid: it means the worker found nothing up to `toBlock`,
+    /// or at `from_block` if the query has no `toBlock`.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let (data, last_block) = datasource.fetch_data(12345, "https://worker.url", query).await?;
+    ///
+    pub async fn fetch_data(
+        &self,
+        from_block: u64,
+        worker_url: &str,
+        query: Value,
+    ) -> Result<(Vec<Value>, u64), DatasourceError> {
+        let batch = self.query_batch(from_block, worker_url, query).await?;
+        Ok((batch.blocks, batch.last_block))
+    }
+
+    /// Does the work of `fetch_data`, also returning the worker URL and response size.
+    async fn query_batch(
+        &self,
+        from_block: u64,
+        worker_url: &str,
+        query: Value,
+    ) -> Result<Batch, DatasourceError> {
+        let json_query = add_from_block(query, from_block);
+        let to_block = json_query.get("toBlock").and_then(Value::as_u64);
+        self.check_rate_limit(RequestKind::Worker).await;
+
+        let range = BlockRange::of_query(&json_query);
+        let result = self.transport.query(worker_url, &json_query).await;
+        telemetry::record_request(Endpoint::Query, &result);
+        let response = result?;
+        let decode_error = |source| DatasourceError::Decode {
+            url: worker_url.to_string(),
+            range,
+            source,
+        };
+        let data: Value = serde_json::from_str(&response).map_err(decode_error)?;
+
+        let mut blocks = match data {
+            Value::Array(blocks) => blocks,
+            payload => {
+                return Err(DatasourceError::ArchiveError {
+                    payload,
+                    url: worker_url.to_string(),
+                    range,
+                })
+            }
+        };
+        let mut last_block = match blocks.last() {
+            Some(block) => block["header"]["number"].as_u64().ok_or_else(|| {
+                decode_error(serde_json::Error::custom(
+                    "Invalid block data format: 'number' field missing or not a u64",
+                ))
+            })?,
+            None => to_block.unwrap_or(from_block),
+        };
+        if let Some(to_block) = to_block.filter(|&to_block| last_block > to_block) {
+            blocks.retain(|block| {
+                block["header"]["number"]
+                    .as_u64()
+                    .is_some_and(|number| number <= to_block)
+            });
+            last_block = to_block;
+        }
+        debug!(
+            bytes = response.len(),
+            blocks = blocks.len(),
+            last_block,
+            "worker response"
+        );
+        Ok(Batch {
+            blocks,
+            last_block,
+            worker_url: Some(worker_url.to_string()),
+            bytes: response.len(),
+        })
+    }
+
+    /// Acquires a permit for making a request, respecting the semaphore limits.
+    async fn acquire_permit(&self) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        let semaphore = self.config.semaphore.as_ref()?;
+        let started = Instant::now();
+        let permit = semaphore.clone().acquire_owned().await.ok();
+        telemetry::record_semaphore_wait(started.elapsed());
+        permit
+    }
+
+    /// Checks the shared rate limiter and the one for this kind of call, waiting if necessary.
+    async fn check_rate_limit(&self, kind: RequestKind) {
+        if let Some(rate_limiter) = &self.config.rate_limiter {
+            rate_limiter.until_ready().await;
+        }
+        let kind_limiter = match kind {
+            RequestKind::Router => &self.config.router_rate_limiter,
+            RequestKind::Worker => &self.config.worker_rate_limiter,
+        };
+        if let Some(rate_limiter) = kind_limiter {
+            rate_limiter.until_ready().await;
+        }
+    }
+
+    /// Runs `operation`, retrying retryable failures according to the configured `RetryPolicy`.
+    async fn with_retry<T, F, Fut>(&self, operation: F) -> Result<T, DatasourceError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, DatasourceError>>,
+    {
+        self.config.retry_policy.retry(operation).await
+    }
+
+    /// Fetches the batch starting at `from_block`, retrying retryable failures according to the
+    /// configured `RetryPolicy`. The batch ends at `to_block` at the latest. With a cache
+    /// configured, finalized batches are served from and stored to disk.
+    #[instrument(level = "debug", skip(self, query))]
+    async fn fetch_batch(
+        &self,
+        query: &Value,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Batch, DatasourceError> {
+        let query = &add_to_block(query.clone(), to_block);
+        let cache = self.config.cache.as_ref().map(|cache| {
+            let key = ResponseCache::key(&self.config.base_url, query, from_block);
+            (cache, key)
+        });
+        let cached = match &cache {
+            Some((cache, key)) => cache.get(key).await,
+            None => None,
+        };
+        if let Some((blocks, last_block)) = cached {
+            debug!(last_block, "served from cache");
+            return Ok(Batch {
+                blocks,
+                last_block,
+                worker_url: None,
+                bytes: 0,
+            });
+        }
+
+        let mut batch = self
+            .with_retry(|| async {
+                let _permit = self.acquire_permit().await;
+
+                if let Some(worker_url) = self.workers.lookup(from_block) {
+                    match self.query_worker(&worker_url, from_block, query).await {
+                        // The worker's range ended; ask the router for the next one.
+                        Err(e) if is_out_of_range(&e) => {}
+                        result => return result,
+                    }
+                }
+                let worker_url = self.assign_worker(from_block).await?;
+                self.query_worker(&worker_url, from_block, query).await
+            })
+            .await?;
+
+        if let Some((cache, key)) = cache {
+            if self.is_finalized(batch.last_block).await {
+                // Compressing and writing the entry blocks, so it runs off the async workers.
+                let (cache, last_block) = (cache.clone(), batch.last_block);
+                let blocks = std::mem::take(&mut batch.blocks);
+                let span = Span::current();
+                let insert = task::spawn_blocking(move || {
+                    let _entered = span.enter();
+                    cache.insert(&key, &blocks, last_block);
+                    blocks
+                });
+                batch.blocks = insert.await.expect("cache insert panicked");
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Queries `worker_url` for the batch starting at `from_block` and records the outcome in the
+    /// worker pool. Transient failures put the worker in cooldown.
+    #[instrument(level = "debug", skip(self, query))]
+    async fn query_worker(
+        &self,
+        worker_url: &str,
+        from_block: u64,
+        query: &Value,
+    ) -> Result<Batch, DatasourceError> {
+        let started = Instant::now();
+        let result = self
+            .query_batch(from_block, worker_url, query.clone())
+            .await;
+        let elapsed = started.elapsed();
+        match &result {
+            Ok(batch) => {
+                debug!(last_block = batch.last_block, ?elapsed, "batch fetched");
+                telemetry::record_worker_response(elapsed, batch.bytes);
+                telemetry::record_batch(
+                    (batch.last_block + 1).saturating_sub(from_block),
+                    &batch.blocks,
+                );
+                self.workers
+                    .record_success(worker_url, from_block, batch.last_block, elapsed);
+            }
+            Err(e) if is_out_of_range(e) => {
+                debug!(error = %e, "worker range ended");
+                self.workers.close_assignment(worker_url, from_block);
+            }
+            Err(e) => {
+                self.workers
+                    .record_failure(worker_url, elapsed, e.is_retryable());
+            }
+        }
+        result
+    }
+
+    /// Asks the router for the worker serving `block`. A worker in cooldown is declined and the
+    /// router asked again, up to `ROUTER_FAILOVER_ATTEMPTS` times, after which it is used anyway.
+    async fn assign_worker(&self, block: u64) -> Result<String, DatasourceError> {
+        let mut worker_url = self.get_worker_url(block).await?;
+        for _ in 1..ROUTER_FAILOVER_ATTEMPTS {
+            if !self.workers.is_cooling_down(&worker_url) {
+                break;
+            }
+            worker_url = self.get_worker_url(block).await?;
+        }
+        Ok(worker_url)
+    }
+
+    /// Returns the latency, error rate and cooldown of every worker used so far.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// for (worker_url, stats) in datasource.worker_stats() {
+    ///     println!("{}: {:?} {:.2}", worker_url, stats.average_latency(), stats.error_rate());
+    /// }
+    ///
+    pub fn worker_stats(&self) -> HashMap<String, WorkerStats> {
+        self.workers.stats()
+    }
+
+    /// Finds the ranges of blocks the archive workers serve within `start_block..=end_block`, in
+    /// block order. The first range starts at `start_block` and the last ends at `end_block`.
+    ///
+    /// The router is asked for the worker of `start_block`, then the end of that worker's range is
+    /// found by binary search, guided by the size of the previous range, asking the worker itself
+    /// whether it serves a block. A worker serves a block if it returns it, and turns away blocks
+    /// outside its range, so replicas or failover in the router do not affect the result. A range
+    /// that reaches `end_block` may go on past it; it is recorded as open and extended by a later
+    /// discovery that goes further. Discovered ranges are remembered, and batches for their blocks
+    /// go straight to their worker.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// for range in datasource.discover_worker_ranges(0, 20_000_000).await? {
+    ///     println!("{}..={}: {}", range.start_block, range.end_block, range.worker_url);
+    /// }
+    ///
+    #[instrument(level = "debug", skip(self))]
+    pub async fn discover_worker_ranges(
+        &self,
+        start_block: u64,
+        end_block: u64,
+    ) -> Result<Vec<WorkerRange>, DatasourceError> {
+        let mut ranges = Vec::new();
+        let mut size_hint = None;
+        let mut from = start_block;
+        while from <= end_block {
+            let (worker_url, range_start, served) = match self.workers.known_range(from) {
+                Some((range, open)) if !open || range.end_block >= end_block => {
+                    size_hint = Some(range.end_block + 1 - range.start_block);
+                    from = range.end_block + 1;
+                    ranges.push(clip(range, start_block, end_block));
+                    continue;
+                }
+                Some((range, _)) => (range.worker_url, range.start_block, range.end_block),
+                None => {
+                    let worker_url = self.with_retry(|| self.get_worker_url(from)).await?;
+                    (worker_url, from, from)
+                }
+            };
+            let to = self
+                .find_worker_range_end(&worker_url, range_start, served, end_block, size_hint)
+                .await?;
+            let range = WorkerRange {
+                worker_url,
+                start_block: range_start,
+                end_block: to,
+            };
+            debug!(?range, "discovered worker range");
+            self.workers.record_range(range.clone(), to == end_block);
+            size_hint = Some(to + 1 - range_start);
+            from = to + 1;
+            ranges.push(clip(range, start_block, end_block));
+        }
+        Ok(ranges)
+    }
+
+    /// Finds the last block up to `end_block` that `worker_url` serves. The worker's range starts
+    /// at `range_start` and is known to include `served`. `size_hint`, the size of the previous
+    /// worker's range, is tried first.
+    async fn find_worker_range_end(
+        &self,
+        worker_url: &str,
+        range_start: u64,
+        served: u64,
+        end_block: u64,
+        size_hint: Option<u64>,
+    ) -> Result<u64, DatasourceError> {
+        // The worker serves `low`, and no block after `high` up to `end_block`.
+        let (mut low, mut high) = (served, end_block);
+        if let Some(guess) = size_hint
+            .map(|size| range_start.saturating_add(size - 1))
+            .filter(|&guess| guess < end_block && guess > served)
+        {
+            if !self.worker_serves(worker_url, guess).await? {
+                high = guess - 1;
+            } else if !self.worker_serves(worker_url, guess + 1).await? {
+                return Ok(guess);
+            } else {
+                low = guess + 1;
+            }
+        }
+        while low < high {
+            let middle = low + (high - low).div_ceil(2);
+            if self.worker_serves(worker_url, middle).await? {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        Ok(low)
+    }
+
+    /// Returns `true` if `worker_url` serves `block`, by asking it for that block's header. A
+    /// worker turns away blocks outside its range, or returns none of them. Any other failure,
+    /// such as a rejected query or auth check, is returned rather than read as a range end.
+    async fn worker_serves(&self, worker_url: &str, block: u64) -> Result<bool, DatasourceError> {
+        let query = json!({
+            "toBlock": block,
+            "includeAllBlocks": true,
+            "fields": {"block": {"number": true}},
+        });
+        self.with_retry(|| async {
+            let _permit = self.acquire_permit().await;
+            match self.query_batch(block, worker_url, query.clone()).await {
+                Ok(batch) => Ok(!batch.blocks.is_empty()),
+                Err(e) if is_out_of_range(&e) => Ok(false),
+                Err(e) => Err(e),
+            }
+        })
+        .await
+    }
+
+    /// Returns `true` if `block` lies below the dataset height. The height is only fetched when
+    /// none has been seen yet; a failed lookup counts as not finalized.
+    async fn is_finalized(&self, block: u64) -> bool {
+        let mut height = self.known_height.load(Ordering::Relaxed);
+        if height == 0 {
+            height = self.get_dataset_height().await.unwrap_or(0);
+        }
+        block < height
+    }
+
+    /// Checks `start_block..=end_block` against the dataset height and returns the end clamped
+    /// to it. A range starting above the height fails with `DatasourceError::RangeBeyondHeight`.
+    /// The height is only fetched when the highest one seen so far is below `end_block`.
+    async fn clamp_to_height(
+        &self,
+        start_block: u64,
+        end_block: u64,
+    ) -> Result<u64, DatasourceError> {
+        if start_block > end_block {
+            return Ok(end_block);
+        }
+        let mut height = self.known_height.load(Ordering::Relaxed);
+        if height < end_block {
+            height = self.with_retry(|| self.get_dataset_height()).await?;
+        }
+        if start_block > height {
+            return Err(DatasourceError::RangeBeyondHeight {
+                range: BlockRange::new(start_block, end_block),
+                height,
+            });
+        }
+        Ok(end_block.min(height))
+    }
+
+    /// Returns the timestamp of `block` in unix seconds, fetching its header unless the
+    /// block-to-time index already has it.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let timestamp = datasource.get_block_timestamp(14_000_000).await?;
+    ///
+    #[instrument(level = "debug", skip(self))]
+    pub async fn get_block_timestamp(&self, block: u64) -> Result<u64, DatasourceError> {
+        if let Some(timestamp) = self.block_times.get(block) {
+            return Ok(timestamp);
+        }
+        let query = json!({
+            "includeAllBlocks": true,
+            "fields": {"block": {"timestamp": true}},
+        });
+        let batch = self.fetch_batch(&query, block, block).await?;
+        for header in batch.blocks.iter().map(|block| &block["header"]) {
+            if let (Some(number), Some(timestamp)) =
+                (header["number"].as_u64(), header["timestamp"].as_u64())
+            {
+                self.block_times.insert(number, timestamp);
+            }
+        }
+        self.block_times
+            .get(block)
+            .ok_or_else(|| DatasourceError::Decode {
+                url: batch
+                    .worker_url
+                    .unwrap_or_else(|| self.config.base_url.clone()),
+                range: Some(BlockRange::new(block, block)),
+                source: serde_json::Error::custom(format!("no timestamp for block {}", block)),
+            })
+    }
+
+    /// Returns the first block with a timestamp at or after `timestamp`, in unix seconds, or
+    /// `None` if the dataset has no such block yet.
+    ///
+    /// The block is found by binary search over block headers. Every header fetched is kept in
+    /// the block-to-time index, so later lookups need fewer requests.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let first_block_of_2022 = datasource.get_block_at_time(1_640_995_200).await?;
+    ///
+    pub async fn get_block_at_time(&self, timestamp: u64) -> Result<Option<u64>, DatasourceError> {
+        let height = self.with_retry(|| self.get_dataset_height()).await?;
+        self.search_block_at_time(timestamp, height).await
+    }
+
+    /// Resolves the time range `from_time..=to_time`, in unix seconds, to the first and last
+    /// blocks produced within it, or `None` if there are none. The result can be passed on to
+    /// `get_data_in_range` or any other range method.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// if let Some((start, end)) = datasource.get_block_range_between(from_time, to_time).await? {
+    ///     let data = datasource.get_data_in_range(query, start, end).await?;
+    /// }
+    ///
+    pub async fn get_block_range_between(
+        &self,
+        from_time: u64,
+        to_time: u64,
+    ) -> Result<Option<(u64, u64)>, DatasourceError> {
+        let height = self.with_retry(|| self.get_dataset_height()).await?;
+        let Some(start_block) = self.search_block_at_time(from_time, height).await? else {
+            return Ok(None);
+        };
+        let end_block = match self
+            .search_block_at_time(to_time.saturating_add(1), height)
+            .await?
+        {
+            Some(block) => block.checked_sub(1),
+            None => Some(height),
+        };
+        Ok(end_block
+            .filter(|&end_block| end_block >= start_block)
+            .map(|end_block| (start_block, end_block)))
+    }
+
+    /// Finds the first block up to `height` with a timestamp at or after `timestamp`.
+    async fn search_block_at_time(
+        &self,
+        timestamp: u64,
+        height: u64,
+    ) -> Result<Option<u64>, DatasourceError> {
+        if self.get_block_timestamp(height).await? < timestamp {
+            return Ok(None);
+        }
+        // The answer lies in `low..=high`: every block before `low` is too early and `high` is not.
+        let (mut low, mut high) = self.block_times.narrow(timestamp, 0, height);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.get_block_timestamp(middle).await? < timestamp {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        Ok(Some(low))
+    }
+
+    /// Streams data in the specified block range, yielding each worker response as it arrives.
+    ///
+    /// Batches are yielded in block order and the next batch is only requested once the previous
+    /// one has been consumed. The stream ends after the first error.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let mut batches = std::pin::pin!(datasource.stream_range(query, 100, 200));
+    /// while let Some(blocks) = batches.next().await {
+    ///     let blocks = blocks?;
+    /// }
+    ///
+    pub fn stream_range(
+        &self,
+        query: Value,
+        start_block: u64,
+        end_block: u64,
+    ) -> impl Stream<Item = Result<Vec<Value>, DatasourceError>> + '_ {
+        let progress = Arc::new(self.progress_tracker(start_block, end_block));
+        self.stream_tracked(query, start_block, end_block, progress)
+    }
+
+    /// Streams data in the specified block range, reporting each batch to `progress`.
+    fn stream_tracked(
+        &self,
+        query: Value,
+        start_block: u64,
+        end_block: u64,
+        progress: Arc<ProgressTracker>,
+    ) -> impl Stream<Item = Result<Vec<Value>, DatasourceError>> + '_ {
+        stream::try_unfold(
+            (query, start_block, None, progress),
+            move |(query, current_block, clamped_end, progress)| async move {
+                let last_block = match clamped_end {
+                    Some(last_block) => last_block,
+                    None => {
+                        let last_block = self.clamp_to_height(start_block, end_block).await?;
+                        progress.clamp_end(last_block);
+                        last_block
+                    }
+                };
+                if current_block > last_block {
+                    return Ok(None);
+                }
+                let batch = self.fetch_batch(&query, current_block, last_block).await?;
+                progress.record_batch(current_block, last_block, &batch);
+                let next_block = batch.last_block + 1;
+                Ok(Some((
+                    batch.blocks,
+                    (query, next_block, Some(last_block), progress),
+                )))
+            },
+        )
+    }
+
+    /// Streams data in the specified block range like `stream_range`, recording progress in a
+    /// checkpoint file so an interrupted fetch can pick up where it stopped.
+    ///
+    /// If `checkpoint` exists the stream continues from the block after the one it records, but
+    /// never before `start_block`; otherwise it starts at `start_block`. A batch is checkpointed once the next one is
+    /// requested, so only blocks the caller has finished with are skipped on resume. A checkpoint
+    /// written for a different query is refused with `DatasourceError::CheckpointMismatch`.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let batches = datasource.stream_range_resumable(query, 0, 20_000_000, "backfill.checkpoint");
+    /// let mut batches = std::pin::pin!(batches);
+    /// while let Some(blocks) = batches.next().await {
+    ///     persist(blocks?)?;
+    /// }
+    ///
+    pub fn stream_range_resumable(
+        &self,
+        query: Value,
+        start_block: u64,
+        end_block: u64,
+        checkpoint: impl Into<PathBuf>,
+    ) -> impl Stream<Item = Result<Vec<Value>, DatasourceError>> + '_ {
+        let state = ResumeState {
+            query,
+            path: checkpoint.into(),
+            next_block: None,
+            fetched: None,
+            last_block: None,
+            progress: self.progress_tracker(start_block, end_block),
+        };
+        stream::try_unfold(state, move |mut state| async move {
+            let last_block = match state.last_block {
+                Some(last_block) => last_block,
+                None => {
+                    let last_block = self.clamp_to_height(start_block, end_block).await?;
+                    state.progress.clamp_end(last_block);
+                    state.last_block = Some(last_block);
+                    last_block
+                }
+            };
+            let next_block = match (state.next_block, state.fetched.take()) {
+                (Some(_), Some(last_block)) => {
+                    Checkpoint::new(&state.query, last_block).save(&state.path)?;
+                    last_block + 1
+                }
+                (Some(next_block), None) => next_block,
+                (None, _) => match Checkpoint::load(&state.path)? {
+                    Some(checkpoint) if !checkpoint.matches(&state.query) => {
+                        return Err(DatasourceError::CheckpointMismatch {
+                            path: state.path,
+                            expected: utils::query_hash(&state.query),
+                            found: checkpoint.query_hash,
+                        });
+                    }
+                    Some(checkpoint) => checkpoint.next_block().max(start_block),
+                    None => start_block,
+                },
+            };
+            state.next_block = Some(next_block);
+            if next_block > last_block {
+                return Ok(None);
+            }
+
+            let batch = self
+                .fetch_batch(&state.query, next_block, last_block)
+                .await?;
+            state.progress.record_batch(next_block, last_block, &batch);
+            state.fetched = Some(batch.last_block);
+            Ok(Some((batch.blocks, state)))
+        })
+    }
+
+    /// Follows the head of the dataset from `from_block`, yielding new blocks as the archive
+    /// makes them available.
+    ///
+    /// Once caught up, the dataset height is polled every `follow_poll_interval`. The stream never
+    /// ends on its own: errors that persist after retries are yielded and following resumes from
+    /// the same block after the poll interval. Cancel `shutdown` to end the stream cleanly; an
+    /// in-flight batch is finished and yielded first.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let shutdown = CancellationToken::new();
+    /// let mut batches = std::pin::pin!(datasource.follow(query, 20_000_000, shutdown.clone()));
+    /// while let Some(blocks) = batches.next().await {
+    ///     let blocks = blocks?;
+    /// }
+    ///
+    pub fn follow(
+        &self,
+        query: Value,
+        from_block: u64,
+        shutdown: CancellationToken,
+    ) -> impl Stream<Item = Result<Vec<Value>, DatasourceError>> + '_ {
+        let state = FollowState {
+            query,
+            next_block: from_block,
+            known_height: None,
+            backoff: false,
+            shutdown,
+        };
+        stream::unfold(state, move |mut state| async move {
+            let result = loop {
+                if state.shutdown.is_cancelled() {
+                    return None;
+                }
+                if state.backoff {
+                    state.backoff = false;
+                    if !self.wait_for_poll(&state.shutdown).await {
+                        return None;
+                    }
+                }
+                match state.known_height {
+                    Some(height) if state.next_block <= height => {
+                        break self
+                            .fetch_batch(&state.query, state.next_block, height)
+                            .await;
+                    }
+                    Some(_) => {
+                        // Caught up: wait before asking the router for a new height.
+                        state.known_height = None;
+                        if !self.wait_for_poll(&state.shutdown).await {
+                            return None;
+                        }
+                    }
+                    None => match self.with_retry(|| self.get_dataset_height()).await {
+                        Ok(height) => state.known_height = Some(height),
+                        Err(e) => break Err(e),
+                    },
+                }
+            };
+
+            match result {
+                Ok(batch) => {
+                    state.next_block = batch.last_block + 1;
+                    Some((Ok(batch.blocks), state))
+                }
+                Err(e) => {
+                    state.known_height = None;
+                    state.backoff = true;
+                    Some((Err(e), state))
+                }
+            }
+        })
+    }
+
+    /// Sleeps for the follow poll interval. Returns false if `shutdown` was cancelled first.
+    async fn wait_for_poll(&self, shutdown: &CancellationToken) -> bool {
+        tokio::select! {
+            _ = shutdown.cancelled() => false,
+            _ = tokio::time::sleep(self.config.follow_poll_interval) => true,
+        }
+    }
+
+    /// Retrieves data in the specified block range.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let data = datasource.get_data_in_range(query, 100, 200).await?;
+    ///
+    pub async fn get_data_in_range(
+        &self,
+        query: Value,
+        start_block: u64,
+        end_block: u64,
+    ) -> Result<Vec<Value>, DatasourceError> {
+        self.stream_range(query, start_block, end_block)
+            .try_concat()
+            .await
+    }
+
+    /// Retrieves data in the specified block range like `get_data_in_range`, stopping early when
+    /// `stop` fires. A batch in flight at that moment is abandoned; the blocks fetched before it
+    /// are returned along with the block to resume from.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let stop = StopSignal::new().with_timeout(Duration::from_secs(60));
+    /// let fetched = datasource.get_data_in_range_until(query, 100, 200, &stop).await?;
+    /// let resume_from = fetched.next_block;
+    ///
+    pub async fn get_data_in_range_until(
+        &self,
+        query: Value,
+        start_block: u64,
+        end_block: u64,
+        stop: &StopSignal,
+    ) -> Result<PartialRange<Vec<Value>>, DatasourceError> {
+        if let Some(reason) = stop.reason() {
+            return Ok(PartialRange {
+                data: Vec::new(),
+                next_block: start_block,
+                stopped: Some(reason),
+            });
+        }
+        let end_block = self.clamp_to_height(start_block, end_block).await?;
+        let progress = self.progress_tracker(start_block, end_block);
+        let (fetched, _) = self
+            .collect_until(&query, start_block, end_block, stop, &progress)
+            .await?;
+        Ok(fetched)
+    }
+
+    /// Fetches batches from `start_block` until `end_block` is reached or `stop` fires. Also
+    /// returns the number of response bytes received.
+    async fn collect_until(
+        &self,
+        query: &Value,
+        start_block: u64,
+        end_block: u64,
+        stop: &StopSignal,
+        progress: &ProgressTracker,
+    ) -> Result<(PartialRange<Vec<Value>>, usize), DatasourceError> {
+        let mut data = Vec::new();
+        let mut bytes = 0;
+        let mut next_block = start_block;
+        while next_block <= end_block {
+            let batch = tokio::select! {
+                biased;
+                reason = stop.stopped() => {
+                    let fetched = PartialRange {
+                        data,
+                        next_block,
+                        stopped: Some(reason),
+                    };
+                    return Ok((fetched, bytes));
+                }
+                batch = self.fetch_batch(query, next_block, end_block) => batch?,
+            };
+            progress.record_batch(next_block, end_block, &batch);
+            next_block = batch.last_block + 1;
+            bytes += batch.bytes;
+            data.extend(batch.blocks);
+        }
+        let fetched = PartialRange {
+            data,
+            next_block: next_block.min(end_block + 1).max(start_block),
+            stopped: None,
+        };
+        Ok((fetched, bytes))
+    }
+
+    /// Retrieves data in the specified block range and converts it to a Polars DataFrame.
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let df = datasource.get_as_df(query, 100, 200).await?;
+    ///
+    pub async fn get_as_df(
+        &self,
+        query: Value,
+        start_block: u64,
+        end_block: u64,
+    ) -> Result<DataFrame, DatasourceError> {
+        let dataset = query_dataset(&query)?;
+        let data = self
+            .get_data_in_range(query.clone(), start_block, end_block)
+            .await?;
+        let fields = to_df::fields::extract_fields(&query);
+        debug!(?fields, blocks = data.len(), "converting to a DataFrame");
+
+        let range = Some(BlockRange::new(start_block, end_block));
+        to_df::to_df(dataset, data, fields).map_err(|source| {
+            let error = DatasourceError::Conversion { range, source };
+            warn!(%error, "conversion failed");
+            error
+        })
+    }
 
-impl Datasource {
-    /// Creates a new `Datasource` with the specified configuration.
+    /// Retrieves data in the specified block range like `get_as_df`, stopping early when `stop`
+    /// fires. The DataFrame holds the blocks fetched before that.
     ///
     /// # Examples
     ///
     /// no_run
-    /// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10);
-    /// let datasource = Datasource::new(config);
+    /// let stop = StopSignal::new().with_cancellation(shutdown.clone());
+    /// let fetched = datasource.get_as_df_until(query, 100, 200, &stop).await?;
     ///
-    pub fn new(config: DatasourceConfig) -> Self {
-        let client = Client::new();
-        Self { client, config }
+    pub async fn get_as_df_until(
+        &self,
+        query: Value,
+        start_block: u64,
+        end_block: u64,
+        stop: &StopSignal,
+    ) -> Result<PartialRange<DataFrame>, DatasourceError> {
+        let dataset = query_dataset(&query)?;
+        let fetched = self
+            .get_data_in_range_until(query.clone(), start_block, end_block, stop)
+            .await?;
+        let df = blocks_to_df(dataset, owned_fields(&query), fetched.data).await?;
+        Ok(PartialRange {
+            data: df,
+            next_block: fetched.next_block,
+            stopped: fetched.stopped,
+        })
     }
 
-    /// Retrieves the current dataset height from the API.
+    /// Retrieves the data of the blocks produced between `from_time` and `to_time`, inclusive and
+    /// in unix seconds, as a Polars DataFrame. The time range is resolved to blocks with
+    /// `get_block_range_between`; if it holds no blocks the DataFrame is empty.
     ///
     /// # Examples
     ///
     /// no_run
-    /// let height = datasource.get_dataset_height().await?;
+    /// // All of January 2022.
+    /// let df = datasource.get_as_df_between(query, 1_640_995_200, 1_643_673_599).await?;
     ///
-    pub async fn get_dataset_height(&self) -> Result<u64, Error> {
-        let url = format!("{}/height", self.config.base_url);
-
-        let response: Value = self.client.get(&url).send().await?.json().await?;
-
-        response
-            .as_u64()
-            .ok_or_else(|| Error::msg("Invalid response format"))
+    pub async fn get_as_df_between(
+        &self,
+        query: Value,
+        from_time: u64,
+        to_time: u64,
+    ) -> Result<DataFrame, DatasourceError> {
+        let dataset = query_dataset(&query)?;
+        match self.get_block_range_between(from_time, to_time).await? {
+            Some((start_block, end_block)) => self.get_as_df(query, start_block, end_block).await,
+            None => blocks_to_df(dataset, owned_fields(&query), Vec::new()).await,
+        }
     }
 
-    /// Retrieves the worker URL for a specific block number.
+    /// Retrieves the data of the blocks produced between `from_time` and `to_time` like
+    /// `get_as_df_between`, stopping early when `stop` fires, including while the time range is
+    /// being resolved to blocks.
+    ///
+    /// `next_block` is the block to resume from with `get_as_df_until`. No block is known until
+    /// the time range is resolved, so a fetch stopped before that returns
+    /// `DatasourceError::TimeRangeUnresolved` and has to be started again. A time range holding
+    /// no blocks returns a complete, empty DataFrame.
     ///
     /// # Examples
     ///
     /// no_run
-    /// let worker_url = datasource.get_worker_url(12345).await?;
+    /// let stop = StopSignal::new().with_timeout(Duration::from_secs(60));
+    /// let fetched = datasource
+    ///     .get_as_df_between_until(query, 1_640_995_200, 1_643_673_599, &stop)
+    ///     .await?;
     ///
-    pub async fn get_worker_url(&self, block_number: u64) -> Result<String, Error> {
-        let url = format!("{}/{}/worker", self.config.base_url, block_number);
-
-        let response: String = self.client.get(&url).send().await?.text().await?;
-        response
-            .parse()
-            .map_err(|e| Error::msg(format!("Error parsing worker URL: {}", e)))
+    pub async fn get_as_df_between_until(
+        &self,
+        query: Value,
+        from_time: u64,
+        to_time: u64,
+        stop: &StopSignal,
+    ) -> Result<PartialRange<DataFrame>, DatasourceError> {
+        let dataset = query_dataset(&query)?;
+        let range = tokio::select! {
+            biased;
+            reason = stop.stopped() => {
+                return Err(DatasourceError::TimeRangeUnresolved { reason });
+            }
+            range = self.get_block_range_between(from_time, to_time) => range?,
+        };
+        match range {
+            Some((start_block, end_block)) => {
+                self.get_as_df_until(query, start_block, end_block, stop)
+                    .await
+            }
+            None => {
+                let df = blocks_to_df(dataset, owned_fields(&query), Vec::new()).await?;
+                Ok(PartialRange {
+                    data: df,
+                    next_block: 0,
+                    stopped: None,
+                })
+            }
+        }
     }
 
-    /// Fetches data from the specified block using the worker URL and query.
+    /// Retrieves data in the specified block range in chunks of `chunk_size` blocks and converts
+    /// each chunk to a Polars DataFrame. Up to `max_concurrent_requests` chunks are fetched
+    /// concurrently; the returned DataFrames are in block order. With `chunk_sizing` configured,
+    /// `chunk_size` is only the size of the first chunks and later ones adapt to the data.
     ///
     /// # Examples
     ///
     /// no_run
-    /// let (data, last_block) = datasource.fetch_data(12345, "https://worker.url", query).await?;
+    /// let dfs = datasource.get_parallelel_chunks(query, 100, 10_000, 1_000).await?;
     ///
-    pub async fn fetch_data(
+    pub async fn get_parallelel_chunks(
         &self,
-        from_block: u64,
-        worker_url: &str,
         query: Value,
-    ) -> Result<(Vec<Value>, u64), Error> {
-        let json_query = add_from_block(query, from_block);
-        let response: String = self
-            .client
-            .post(worker_url)
-            .json(&json_query)
-            .send()
-            .await?
-            .text()
+        start_block: u64,
+        end_block: u64,
+        chunk_size: u64,
+    ) -> Result<Vec<DataFrame>, DatasourceError> {
+        let fetched = self
+            .get_parallelel_chunks_until(
+                query,
+                start_block,
+                end_block,
+                chunk_size,
+                &StopSignal::new(),
+            )
             .await?;
-        let data: Value = serde_json::from_str(&response)?;
-        if data.as_array().is_none() {
-            println!("DATA: {:?}", data);
-        }
-
-        let blocks = data
-            .as_array()
-            .ok_or_else(|| Error::msg("Invalid JSON format: Expected an array got {:?}"))?;
-        let last_block = blocks
-            .last()
-            .and_then(|b| b["header"]["number"].as_u64())
-            .ok_or_else(|| {
-                Error::msg("Invalid block data format: 'number' field missing or not a u64")
-            })?;
-        Ok((blocks.to_vec(), last_block))
+        Ok(fetched.data)
     }
 
-    /// Acquires a permit for making a request, respecting the semaphore limits.
-    async fn acquire_permit(&self) -> Option<tokio::sync::OwnedSemaphorePermit> {
-        if let Some(semaphore) = &self.config.semaphore {
-            semaphore.clone().acquire_owned().await.ok()
-        } else {
-            None
-        }
-    }
-
-    /// Checks the rate limiter and waits if necessary.
-    async fn check_rate_limit(&self) {
-        if let Some(rate_limiter) = &self.config.rate_limiter {
-            rate_limiter.until_ready().await;
-        }
-    }
-
-    /// Retrieves data in the specified block range.
+    /// Retrieves data in chunks like `get_parallelel_chunks`, stopping early when `stop` fires.
+    ///
+    /// The DataFrames returned cover one contiguous run of blocks from `start_block`: every chunk
+    /// before the first unfinished one, followed by the part of that chunk fetched so far. Blocks
+    /// fetched for later chunks are discarded, so resuming from `next_block` leaves no gaps.
     ///
     /// # Examples
     ///
     /// no_run
-    /// let data = datasource.get_data_in_range(query, 100, 200).await?;
+    /// let stop = StopSignal::new().with_timeout(Duration::from_secs(600));
+    /// let fetched = datasource
+    ///     .get_parallelel_chunks_until(query, 100, 10_000, 1_000, &stop)
+    ///     .await?;
     ///
-    pub async fn get_data_in_range(
+    pub async fn get_parallelel_chunks_until(
         &self,
         query: Value,
         start_block: u64,
         end_block: u64,
-    ) -> Result<Vec<Value>, Error> {
-        let mut current_block = start_block;
-        let mut all_data = Vec::new();
+        chunk_size: u64,
+        stop: &StopSignal,
+    ) -> Result<PartialRange<Vec<DataFrame>>, DatasourceError> {
+        query_dataset(&query)?;
+        if let Some(reason) = stop.reason() {
+            return Ok(PartialRange {
+                data: Vec::new(),
+                next_block: start_block,
+                stopped: Some(reason),
+            });
+        }
+        let end_block = self.clamp_to_height(start_block, end_block).await?;
+        let concurrency = self.config.max_concurrent_requests.max(1);
+        let progress = &self.progress_tracker(start_block, end_block);
+        let query = &query;
+        let mut planner = ChunkPlanner::new(
+            start_block,
+            end_block,
+            chunk_size,
+            self.config.chunk_sizing.clone(),
+        );
+        if self.config.align_chunks_to_workers && start_block <= end_block {
+            let ranges = self.discover_worker_ranges(start_block, end_block).await?;
+            planner = planner.with_boundaries(ranges.iter().skip(1).map(|range| range.start_block));
+        }
 
-        while current_block <= end_block {
-            self.check_rate_limit().await;
-            let _permit = self.acquire_permit().await;
+        // Chunks are planned one at a time as others complete, so each is sized from the
+        // samples of the chunks before it.
+        let mut in_flight = FuturesUnordered::new();
+        let mut chunks = Vec::new();
+        loop {
+            while in_flight.len() < concurrency && stop.reason().is_none() {
+                let Some((chunk_start, chunk_end)) = planner.next_chunk() else {
+                    break;
+                };
+                in_flight.push(async move {
+                    let chunk = self
+                        .get_chunk_as_df(query, chunk_start, chunk_end, stop, progress)
+                        .await;
+                    (chunk_start, chunk)
+                });
+            }
+            let Some((chunk_start, chunk)) = in_flight.next().await else {
+                break;
+            };
+            let (chunk, sample) = chunk?;
+            planner.record(sample);
+            chunks.push((chunk_start, chunk));
+        }
+        chunks.sort_by_key(|(chunk_start, _)| *chunk_start);
 
-            let worker_url = self.get_worker_url(current_block).await?;
+        let mut frames = Vec::new();
+        for (_, chunk) in chunks {
+            frames.extend(chunk.data);
+            if chunk.stopped.is_some() {
+                return Ok(PartialRange {
+                    data: frames,
+                    next_block: chunk.next_block,
+                    stopped: chunk.stopped,
+                });
+            }
+        }
+        if !planner.is_done() {
+            // The signal fired between chunks, before the rest of the range was requested.
+            return Ok(PartialRange {
+                data: frames,
+                next_block: planner.next_block(),
+                stopped: stop.reason(),
+            });
+        }
+        Ok(PartialRange {
+            data: frames,
+            next_block: (end_block + 1).max(start_block),
+            stopped: None,
+        })
+    }
 
-            let (data, last_block) = self
-                .fetch_data(current_block, &worker_url, query.clone())
-                .await?;
-            all_data.extend(data);
-            current_block = last_block + 1;
+    /// Fetches a single chunk and converts it to a DataFrame on the blocking thread pool. A chunk
+    /// stopped before any blocks were fetched has no DataFrame. Also returns what the chunk held,
+    /// for sizing the chunks after it.
+    async fn get_chunk_as_df(
+        &self,
+        query: &Value,
+        chunk_start: u64,
+        chunk_end: u64,
+        stop: &StopSignal,
+        progress: &ProgressTracker,
+    ) -> Result<(PartialRange<Option<DataFrame>>, ChunkSample), DatasourceError> {
+        let (fetched, bytes) = self
+            .collect_until(query, chunk_start, chunk_end, stop, progress)
+            .await?;
+        let sample = ChunkSample {
+            blocks: fetched.next_block - chunk_start,
+            bytes: bytes as u64,
+            items: telemetry::dataset_items(&fetched.data)
+                .map(|(_, items)| items as u64)
+                .sum(),
+        };
+        if fetched.stopped.is_some() && fetched.data.is_empty() {
+            return Ok((fetched.map(|_| None), sample));
         }
 
-        Ok(all_data)
+        let dataset = query_dataset(query)?;
+        let df = blocks_to_df(dataset, owned_fields(query), fetched.data).await?;
+        let chunk = PartialRange {
+            data: Some(df),
+            next_block: fetched.next_block,
+            stopped: fetched.stopped,
+        };
+        Ok((chunk, sample))
     }
 
-    /// Retrieves data in the specified block range and converts it to a Polars DataFrame.
+    /// Streams data in the specified block range, converting each worker response to a Polars
+    /// DataFrame as it arrives. Every DataFrame has the same columns and types, including those
+    /// built from batches without any matching items.
     ///
     /// # Examples
     ///
     /// no_run
-    /// let df = datasource.get_as_df(query, 100, 200).await?;
+    /// let mut frames = std::pin::pin!(datasource.stream_as_df(query, 100, 200));
+    /// while let Some(df) = frames.next().await {
+    ///     let df = df?;
+    /// }
     ///
-    pub async fn get_as_df(
+    pub fn stream_as_df(
         &self,
         query: Value,
         start_block: u64,
         end_block: u64,
-    ) -> Result<DataFrame, Error> {
-        let data = self
-            .get_data_in_range(query.clone(), start_block, end_block)
-            .await?;
-        //println!("DATA: {:?}", data);
-        let fields = to_df::fields::extract_fields(&query);
-        println!("FIELDS: {:?}", fields);
-        let dataset = to_df::fields::get_dataset(&query);
+    ) -> impl Stream<Item = Result<DataFrame, DatasourceError>> + '_ {
+        let dataset = match query_dataset(&query) {
+            Ok(dataset) => dataset,
+            Err(e) => return stream::once(future::ready(Err(e))).right_stream(),
+        };
+        let fields = owned_fields(&query);
+        self.stream_range(query, start_block, end_block)
+            .and_then(move |data| blocks_to_df(dataset, fields.clone(), data))
+            .left_stream()
+    }
+}
+
+/// A worker response: its blocks, the last block scanned, the worker that served it (`None` for
+/// cached responses) and the size of the response body.
+struct Batch {
+    blocks: Vec<Value>,
+    last_block: u64,
+    worker_url: Option<String>,
+    bytes: usize,
+}
 
-        let df = to_df::to_df(dataset, data, fields).unwrap();
-        Ok(df)
+impl ProgressTracker {
+    fn record_batch(&self, from_block: u64, end_block: u64, batch: &Batch) {
+        self.record(
+            from_block,
+            batch.last_block,
+            end_block,
+            &batch.blocks,
+            batch.bytes,
+            batch.worker_url.as_deref(),
+        );
     }
-    pub async fn get_parallelel_chunks(
-        &self,
-        query: Value,
-        start_block: u64,
-        end_block: u64,
-        chunk_size: u64,
-    ) -> Result<Vec<DataFrame>, Error> {
-        let ranges = utils::compute_chunk_ranges(start_block, end_block, chunk_size);
-        let mut dfs = Vec::new();
+}
 
-        Ok(dfs)
+/// State carried between the batches of `Datasource::stream_range_resumable`. `next_block` is
+/// `None` until the checkpoint has been read and `fetched` holds the last block of the batch
+/// yielded most recently, which has not been checkpointed yet.
+struct ResumeState {
+    query: Value,
+    path: PathBuf,
+    next_block: Option<u64>,
+    fetched: Option<u64>,
+    /// The end of the range clamped to the dataset height, once checked.
+    last_block: Option<u64>,
+    progress: ProgressTracker,
+}
+
+/// State carried between the batches of `Datasource::follow`.
+struct FollowState {
+    query: Value,
+    next_block: u64,
+    known_height: Option<u64>,
+    backoff: bool,
+    shutdown: CancellationToken,
+}
+
+/// Returns the dataset `query` converts to a DataFrame from, or
+/// `DatasourceError::UnsupportedQuery` if there is none, such as for a traces-only query.
+pub(crate) fn query_dataset(query: &Value) -> Result<Dataset, DatasourceError> {
+    to_df::fields::get_dataset(query).map_err(|e| DatasourceError::UnsupportedQuery {
+        reason: e.to_string(),
+    })
+}
+
+/// Returns the fields selected by the query as owned strings.
+pub(crate) fn owned_fields(query: &Value) -> Vec<String> {
+    to_df::fields::extract_fields(query)
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// Returns `true` if a worker turned a request away because the requested block is outside the
+/// range it serves: a `404`, or a `400` saying the block is out of range. Other client errors,
+/// such as an invalid query or a failed auth check, are not.
+fn is_out_of_range(error: &DatasourceError) -> bool {
+    match error {
+        DatasourceError::Http { status, body, .. } => {
+            *status == StatusCode::NOT_FOUND
+                || (*status == StatusCode::BAD_REQUEST
+                    && ["out of range", "not in the range"]
+                        .iter()
+                        .any(|phrase| body.contains(phrase)))
+        }
+        _ => false,
+    }
+}
+
+/// Clips `range` to `start_block..=end_block`.
+fn clip(range: WorkerRange, start_block: u64, end_block: u64) -> WorkerRange {
+    WorkerRange {
+        start_block: range.start_block.max(start_block),
+        end_block: range.end_block.min(end_block),
+        ..range
     }
 }
 
+/// Converts blocks to a DataFrame on the blocking thread pool.
+pub(crate) async fn blocks_to_df(
+    dataset: Dataset,
+    fields: Vec<String>,
+    data: Vec<Value>,
+) -> Result<DataFrame, DatasourceError> {
+    let range = batch_range(&data);
+    let span = Span::current();
+    let conversion = task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let fields = fields.iter().map(String::as_str).collect();
+        to_df::to_df(dataset, data, fields)
+    });
+    match conversion.await {
+        Ok(result) => result,
+        Err(e) => Err(e.into()),
+    }
+    .map_err(|source| {
+        let error = DatasourceError::Conversion { range, source };
+        warn!(%error, "conversion failed");
+        error
+    })
+}
+
+/// Returns the range from the first to the last block of a batch.
+fn batch_range(data: &[Value]) -> Option<BlockRange> {
+    let number = |block: &Value| block["header"]["number"].as_u64();
+    Some(BlockRange::new(
+        number(data.first()?)?,
+        number(data.last()?)?,
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::query_builder::{
@@ -233,75 +1671,509 @@ mod tests {
     };
 
     use super::*;
+    use crate::mock_archive::{MockArchive, MockArchiveServer};
+    use crate::stop::StopReason;
+    use crate::transport::{MockRequest, MockResponse, MockTransport};
     use serde_json::json;
     use tokio::runtime::Runtime;
 
     const BASE_URL: &str = "https://v2.archive.subsquid.io/network/ethereum-mainnet";
 
+    fn mock_datasource(transport: Arc<MockTransport>) -> Datasource {
+        let config =
+            DatasourceConfig::new(BASE_URL.to_string(), 10).with_retry_policy(RetryPolicy {
+                base_delay: Duration::ZERO,
+                ..Default::default()
+            });
+        Datasource::with_transport(config, transport)
+    }
+
+    fn mock_blocks(numbers: std::ops::RangeInclusive<u64>) -> MockResponse {
+        let blocks: Vec<Value> = numbers
+            .map(|number| json!({"header": {"number": number}}))
+            .collect();
+        MockResponse::json(json!(blocks))
+    }
+
+    /// A transport whose first `/height` call answers `height`.
+    fn mock_transport(height: u64) -> Arc<MockTransport> {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_height(MockResponse::json(json!(height)));
+        transport
+    }
+
+    fn worker(url: &str) -> MockResponse {
+        MockResponse::Body(url.to_string())
+    }
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/blocks.json");
+
+    /// A mock archive serving the fixture blocks from workers of 10 blocks, 4 blocks a response.
+    async fn fixture_archive() -> MockArchiveServer {
+        MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_worker_range_size(10)
+            .with_max_blocks_per_response(4)
+            .start()
+            .await
+            .unwrap()
+    }
+
+    fn archive_datasource(server: &MockArchiveServer, max_concurrent: usize) -> Datasource {
+        Datasource::new(DatasourceConfig::new(server.url(), max_concurrent))
+    }
+
+    #[tokio::test]
+    async fn test_get_data_in_range_with_mock_transport() {
+        let transport = mock_transport(100);
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_query(mock_blocks(1..=3));
+        transport.push_query(mock_blocks(4..=6));
+        let api = mock_datasource(transport.clone());
+
+        let data = api.get_data_in_range(json!({}), 1, 5).await.unwrap();
+        // Block 6 is past the end of the range and dropped.
+        assert_eq!(data.len(), 5);
+        // The worker that served blocks 1..=3 is reused for block 4 without asking the router.
+        assert_eq!(
+            transport.requests(),
+            vec![
+                MockRequest::Height,
+                MockRequest::Worker { block: 1 },
+                MockRequest::Query {
+                    worker_url: "http://worker-1".to_string(),
+                    query: json!({"fromBlock": 1, "toBlock": 5}),
+                },
+                MockRequest::Query {
+                    worker_url: "http://worker-1".to_string(),
+                    query: json!({"fromBlock": 4, "toBlock": 5}),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_asks_router_for_fresh_worker() {
+        let transport = mock_transport(100);
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_query(MockResponse::Status(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "overloaded".to_string(),
+        ));
+        transport.push_worker(worker("http://worker-2"));
+        transport.push_query(MockResponse::Body("[{\"header\": {\"num".to_string()));
+        transport.push_worker(worker("http://worker-3"));
+        transport.push_query(mock_blocks(1..=2));
+        let api = mock_datasource(transport.clone());
+
+        let data = api.get_data_in_range(json!({}), 1, 2).await.unwrap();
+        assert_eq!(data.len(), 2);
+        let workers: Vec<String> = transport
+            .requests()
+            .into_iter()
+            .filter_map(|request| match request {
+                MockRequest::Query { worker_url, .. } => Some(worker_url),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            workers,
+            vec!["http://worker-1", "http://worker-2", "http://worker-3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_worker_out_of_range_asks_router() {
+        let transport = mock_transport(100);
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_query(mock_blocks(1..=3));
+        transport.push_query(MockResponse::Status(
+            StatusCode::BAD_REQUEST,
+            "block 4 is out of range".to_string(),
+        ));
+        transport.push_worker(worker("http://worker-2"));
+        transport.push_query(mock_blocks(4..=6));
+        let api = mock_datasource(transport.clone());
+
+        let data = api.get_data_in_range(json!({}), 1, 6).await.unwrap();
+        assert_eq!(data.len(), 6);
+        assert_eq!(transport.requests()[4], MockRequest::Worker { block: 4 });
+        // Running past the end of a worker's range is not a failure of the worker.
+        let stats = api.worker_stats();
+        assert_eq!(stats["http://worker-1"].failures, 0);
+        assert_eq!(stats["http://worker-1"].error_rate(), 0.0);
+        assert!(stats["http://worker-1"].cooldown_until.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_rerouted() {
+        let transport = mock_transport(100);
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_query(MockResponse::Status(
+            StatusCode::UNAUTHORIZED,
+            "missing token".to_string(),
+        ));
+        let api = mock_datasource(transport.clone());
+
+        let error = api.get_data_in_range(json!({}), 1, 6).await.unwrap_err();
+        assert!(matches!(
+            error,
+            DatasourceError::Http {
+                status: StatusCode::UNAUTHORIZED,
+                ..
+            }
+        ));
+        assert!(!error.is_retryable());
+        // Neither retried nor sent to the router for another worker.
+        assert_eq!(transport.requests().len(), 3);
+        assert_eq!(api.worker_stats()["http://worker-1"].failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_failing_worker_is_skipped_during_cooldown() {
+        let transport = mock_transport(100);
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_query(MockResponse::Status(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "overloaded".to_string(),
+        ));
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_worker(worker("http://worker-2"));
+        transport.push_query(mock_blocks(1..=2));
+        let api = mock_datasource(transport.clone());
+
+        let data = api.get_data_in_range(json!({}), 1, 2).await.unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(
+            transport.requests()[5],
+            MockRequest::Query {
+                worker_url: "http://worker-2".to_string(),
+                query: json!({"fromBlock": 1, "toBlock": 2}),
+            }
+        );
+        assert!(api.worker_stats()["http://worker-1"]
+            .cooldown_until
+            .is_some());
+    }
+
+    /// Cancels a stop signal once a number of batches have completed.
+    struct CancelAfter(std::sync::atomic::AtomicUsize, StopSignal);
+
+    impl ProgressObserver for CancelAfter {
+        fn on_progress(&self, _: &crate::progress::Progress) {
+            if self.0.fetch_sub(1, Ordering::SeqCst) == 1 {
+                self.1.cancel();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_returns_partial_data() {
+        let transport = mock_transport(100);
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_query(mock_blocks(1..=3));
+        transport.push_query(mock_blocks(4..=6));
+        let stop = StopSignal::new();
+        let api = mock_datasource(transport.clone())
+            .with_progress_observer(Arc::new(CancelAfter(1.into(), stop.clone())));
+
+        let fetched = api
+            .get_data_in_range_until(json!({}), 1, 6, &stop)
+            .await
+            .unwrap();
+        assert_eq!(fetched.data.len(), 3);
+        assert_eq!(fetched.next_block, 4);
+        assert_eq!(fetched.stopped, Some(StopReason::Cancelled));
+        assert_eq!(transport.requests().len(), 3);
+
+        let resumed = api
+            .get_data_in_range_until(json!({}), fetched.next_block, 6, &StopSignal::new())
+            .await
+            .unwrap();
+        assert!(resumed.is_complete());
+        assert_eq!(resumed.data.len(), 3);
+        assert_eq!(resumed.next_block, 7);
+    }
+
+    #[tokio::test]
+    async fn test_range_is_clamped_to_height() {
+        let transport = mock_transport(4);
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_query(mock_blocks(1..=4));
+        let api = mock_datasource(transport.clone());
+
+        let data = api.get_data_in_range(json!({}), 1, 10).await.unwrap();
+        assert_eq!(data.len(), 4);
+        assert_eq!(
+            transport.requests()[2],
+            MockRequest::Query {
+                worker_url: "http://worker-1".to_string(),
+                query: json!({"fromBlock": 1, "toBlock": 4}),
+            }
+        );
+
+        transport.push_height(MockResponse::json(json!(4)));
+        let error = api.get_data_in_range(json!({}), 5, 10).await.unwrap_err();
+        assert!(matches!(
+            error,
+            DatasourceError::RangeBeyondHeight { height: 4, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_empty_batch_has_no_data() {
+        let transport = mock_transport(100);
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_query(MockResponse::json(json!([])));
+        let api = mock_datasource(transport.clone());
+
+        let data = api.get_data_in_range(json!({}), 1, 3).await.unwrap();
+        assert!(data.is_empty());
+        // The empty batch ends at `toBlock`, without a retry or a second worker.
+        assert_eq!(transport.requests().len(), 3);
+        assert_eq!(api.worker_stats()["http://worker-1"].failures, 0);
+
+        transport.push_query(MockResponse::json(json!([])));
+        let (data, last_block) = api
+            .fetch_data(7, "http://worker-1", json!({"toBlock": 9}))
+            .await
+            .unwrap();
+        assert!(data.is_empty());
+        assert_eq!(last_block, 9);
+    }
+
+    #[tokio::test]
+    async fn test_expired_deadline_fetches_nothing() {
+        let transport = Arc::new(MockTransport::new());
+        let api = mock_datasource(transport.clone());
+        let stop = StopSignal::new().with_timeout(Duration::ZERO);
+
+        let fetched = api
+            .get_data_in_range_until(json!({}), 1, 6, &stop)
+            .await
+            .unwrap();
+        assert!(fetched.data.is_empty());
+        assert_eq!(fetched.next_block, 1);
+        assert_eq!(fetched.stopped, Some(StopReason::DeadlineExceeded));
+        assert!(transport.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fatal_error_is_not_retried() {
+        let transport = mock_transport(100);
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_query(MockResponse::json(json!({"error": "invalid query"})));
+        let api = mock_datasource(transport.clone());
+
+        let error = api.get_data_in_range(json!({}), 1, 2).await.unwrap_err();
+        assert!(matches!(error, DatasourceError::ArchiveError { .. }));
+        assert_eq!(error.url(), Some("http://worker-1"));
+        assert_eq!(transport.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_checkpoint() {
+        let path = std::env::temp_dir().join(format!("dive-resume-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let query = json!({"logs": [{"address": ["0x1"]}]});
+
+        let transport = mock_transport(100);
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_query(mock_blocks(1..=3));
+        transport.push_query(MockResponse::json(json!({"error": "worker crashed"})));
+        let api = mock_datasource(transport);
+        let results: Vec<_> = api
+            .stream_range_resumable(query.clone(), 1, 9, &path)
+            .collect()
+            .await;
+        assert_eq!(results.len(), 2);
+        assert!(results[1].is_err());
+        assert_eq!(
+            Checkpoint::load(&path).unwrap(),
+            Some(Checkpoint::new(&query, 3))
+        );
+
+        let transport = mock_transport(100);
+        transport.push_worker(worker("http://worker-2"));
+        transport.push_query(mock_blocks(4..=9));
+        let api = mock_datasource(transport.clone());
+        let data: Vec<Value> = api
+            .stream_range_resumable(query.clone(), 1, 9, &path)
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(data.len(), 6);
+        assert_eq!(transport.requests()[1], MockRequest::Worker { block: 4 });
+        assert_eq!(Checkpoint::load(&path).unwrap().unwrap().last_block, 9);
+
+        let error = api
+            .stream_range_resumable(json!({"logs": [{"address": ["0x2"]}]}), 1, 9, &path)
+            .try_concat()
+            .await
+            .unwrap_err();
+        assert!(matches!(error, DatasourceError::CheckpointMismatch { .. }));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_does_not_start_before_start_block() {
+        let path =
+            std::env::temp_dir().join(format!("dive-resume-later-{}.json", std::process::id()));
+        let query = json!({"logs": [{"address": ["0x1"]}]});
+        Checkpoint::new(&query, 3).save(&path).unwrap();
+
+        let transport = mock_transport(100);
+        transport.push_worker(worker("http://worker-1"));
+        transport.push_query(mock_blocks(20..=25));
+        let api = mock_datasource(transport.clone());
+        let data: Vec<Value> = api
+            .stream_range_resumable(query, 20, 25, &path)
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(data.len(), 6);
+        assert_eq!(transport.requests()[1], MockRequest::Worker { block: 20 });
+        assert_eq!(Checkpoint::load(&path).unwrap().unwrap().last_block, 25);
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[tokio::test]
     async fn test_get_dataset_height() {
-        let config = DatasourceConfig::new(BASE_URL.to_string(), 10);
-        let api = Datasource::new(config);
+        let server = fixture_archive().await;
+        let api = archive_datasource(&server, 10);
 
-        let height = api.get_dataset_height().await;
-        assert!(height.is_ok());
-        let height = height.unwrap();
-        assert!(height > 0, "Dataset height should be greater than 0");
+        let height = api.get_dataset_height().await.unwrap();
+        assert_eq!(height, 14000029);
     }
 
     #[tokio::test]
     async fn test_get_worker_url() {
-        let config = DatasourceConfig::new(BASE_URL.to_string(), 10);
-        let api = Datasource::new(config);
+        let server = fixture_archive().await;
+        let api = archive_datasource(&server, 10);
 
-        let block_number = 1;
-        let worker_url = api.get_worker_url(block_number).await;
-        assert!(worker_url.is_ok());
-        let worker_url = worker_url.unwrap();
-        assert!(
-            worker_url.starts_with("http"),
-            "Worker URL should start with http"
-        );
+        let worker_url = api.get_worker_url(14000012).await.unwrap();
+        assert!(worker_url.starts_with(&server.url()));
+        assert_eq!(server.worker_range(&worker_url), Some((14000010, 14000019)));
     }
 
     #[tokio::test]
     async fn test_fetch_data() {
-        let config = DatasourceConfig::new(BASE_URL.to_string(), 10);
-        let api = Datasource::new(config);
+        let server = fixture_archive().await;
+        let api = archive_datasource(&server, 10);
 
-        let worker_url = api.get_worker_url(1).await.unwrap();
-        let query = json!({});
-        let (data, last_block) = api.fetch_data(1, &worker_url, query).await.unwrap();
+        let worker_url = api.get_worker_url(14000000).await.unwrap();
+        let (data, last_block) = api
+            .fetch_data(14000000, &worker_url, json!({}))
+            .await
+            .unwrap();
 
-        assert!(!data.is_empty(), "Data should not be empty");
-        assert!(last_block > 0, "Last block should be greater than 0");
+        // A response covers at most 4 blocks.
+        assert_eq!(data.len(), 4);
+        assert_eq!(last_block, 14000003);
     }
 
     #[tokio::test]
     async fn test_get_data_in_range() {
-        let config = DatasourceConfig::new(BASE_URL.to_string(), 10);
-        let api = Datasource::new(config);
+        let server = fixture_archive().await;
+        let api = archive_datasource(&server, 10);
 
-        let query = json!({});
-        let start_block = 1;
-        let end_block = 10;
         let data = api
-            .get_data_in_range(query, start_block, end_block)
+            .get_data_in_range(json!({}), 14000000, 14000012)
+            .await
+            .unwrap();
+
+        let numbers: Vec<u64> = data
+            .iter()
+            .map(|block| block["header"]["number"].as_u64().unwrap())
+            .collect();
+        assert_eq!(numbers, (14000000..=14000012).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_stream_range() {
+        let server = fixture_archive().await;
+        let api = archive_datasource(&server, 10);
+
+        let batches: Vec<Vec<Value>> = api
+            .stream_range(json!({}), 14000000, 14000012)
+            .try_collect()
+            .await
+            .unwrap();
+
+        // One batch per response: 4 blocks each, cut short at the worker range end and at the
+        // end of the range.
+        let sizes: Vec<usize> = batches.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![4, 4, 2, 3]);
+        let numbers: Vec<u64> = batches
+            .iter()
+            .flatten()
+            .map(|block| block["header"]["number"].as_u64().unwrap())
+            .collect();
+        assert_eq!(numbers, (14000000..=14000012).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_stream_as_df() {
+        let server = fixture_archive().await;
+        let api = archive_datasource(&server, 10);
+
+        let query = json!({
+            "logs": [{
+                "address": ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"]
+            }],
+            "fields": {
+                "log": {
+                    "address": true,
+                    "topics": true,
+                    "data": true
+                }
+            },
+        });
+        let dfs: Vec<DataFrame> = api
+            .stream_as_df(query, 14000000, 14000010)
+            .try_collect()
             .await
             .unwrap();
 
-        assert!(!data.is_empty(), "Data should not be empty");
-        let last_block = data.last().unwrap()["header"]["number"].as_u64().unwrap();
+        assert!(dfs.len() > 1, "Stream should yield a DataFrame per batch");
+        let schema = dfs[0].schema();
+        assert!(dfs.iter().all(|df| df.schema() == schema));
+        assert_eq!(dfs.iter().map(|df| df.height()).sum::<usize>(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_follow() {
+        let server = fixture_archive().await;
+        let config = DatasourceConfig::new(server.url(), 10)
+            .with_follow_poll_interval(Duration::from_millis(20));
+        let api = Datasource::new(config);
+
+        let height = api.get_dataset_height().await.unwrap();
+        let shutdown = CancellationToken::new();
+        let mut batches = std::pin::pin!(api.follow(json!({}), height - 5, shutdown.clone()));
+
+        let mut numbers = Vec::new();
+        while numbers.last() != Some(&height) {
+            let batch = batches.next().await.unwrap().unwrap();
+            numbers.extend(
+                batch
+                    .iter()
+                    .map(|block| block["header"]["number"].as_u64().unwrap()),
+            );
+        }
+        assert_eq!(numbers, (height - 5..=height).collect::<Vec<_>>());
+
+        shutdown.cancel();
         assert!(
-            last_block >= end_block,
-            "Last block should be at least the end block"
+            batches.next().await.is_none(),
+            "Stream should end on shutdown"
         );
     }
 
     #[tokio::test]
     async fn test_get_as_df() {
-        let config = DatasourceConfig::new(BASE_URL.to_string(), 10);
-        let api = Datasource::new(config);
+        let server = fixture_archive().await;
+        let api = archive_datasource(&server, 10);
 
         let query = json!({"logs": [
               {
@@ -322,16 +2194,48 @@ mod tests {
 
             },
         });
-        //println!("GOOD QUERY: {:?}", query);
         let start_block = 14000000;
         let end_block = 14000001;
         let df = api.get_as_df(query, start_block, end_block).await.unwrap();
+        assert_eq!(df.height(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_parallelel_chunks() {
+        let server = fixture_archive().await;
+        let api = archive_datasource(&server, 4);
+
+        let query = json!({
+            "logs": [{
+                "address": ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"],
+                "topic0": [
+                  "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+                ]
+            }],
+            "fields": {
+                "log": {
+                    "address": true,
+                    "topics": true,
+                    "data": true
+                }
+            },
+        });
+        let dfs = api
+            .get_parallelel_chunks(query.clone(), 14000000, 14000009, 3)
+            .await
+            .unwrap();
+        assert_eq!(dfs.len(), 4, "One DataFrame per chunk");
+
+        let sequential = api.get_as_df(query, 14000000, 14000009).await.unwrap();
+        let total_rows: usize = dfs.iter().map(|df| df.height()).sum();
+        assert_eq!(total_rows, sequential.height());
+        assert_eq!(total_rows, 10);
     }
 
     #[tokio::test]
     async fn test_with_querybuilder() {
-        let config = DatasourceConfig::new(BASE_URL.to_string(), 10);
-        let api = Datasource::new(config);
+        let server = fixture_archive().await;
+        let api = archive_datasource(&server, 10);
 
         let log_request = LogRequest {
             address: Some(vec![
@@ -359,20 +2263,18 @@ mod tests {
         let start_block = 14000005;
         let end_block = 14000006;
 
-        //println!("QUERY: {:?}", query);
         let df = api.get_as_df(query, start_block, end_block).await.unwrap();
-
-        // println!("{:?}", df);
+        assert_eq!(df.height(), 2);
     }
 
     #[tokio::test]
     async fn test_tx_with_querybuilder() {
-        let config = DatasourceConfig::new(BASE_URL.to_string(), 10);
-        let api = Datasource::new(config);
+        let server = fixture_archive().await;
+        let api = archive_datasource(&server, 10);
 
         let tx_request = TransactionRequest {
             to: Some(vec![
-                "0x0000000000000000000000000000000000000000".to_string()
+                "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48".to_string()
             ]),
             ..Default::default()
         };
@@ -393,16 +2295,14 @@ mod tests {
         let start_block = 14000005;
         let end_block = 14000006;
 
-        //println!("QUERY: {:?}", query);
         let df = api.get_as_df(query, start_block, end_block).await.unwrap();
-        //println!("TXS");
-        //println!("{:?}", df);
+        assert_eq!(df.height(), 2);
     }
 
     #[tokio::test]
     async fn test_trace_with_querybuilder() {
-        let config = DatasourceConfig::new(BASE_URL.to_string(), 10);
-        let api = Datasource::new(config);
+        let server = fixture_archive().await;
+        let api = archive_datasource(&server, 10);
 
         let trace_request = TraceRequest {
             type_: Some(vec!["call".to_string()]),
@@ -424,9 +2324,11 @@ mod tests {
         let query = query_builder.build();
         let start_block = 14000005;
         let end_block = 14000006;
-        println!("TRACE QUERY: {:?}", query);
-        let df = api.get_as_df(query, start_block, end_block).await.unwrap();
-        println!("TRACES");
-        println!("{:?}", df);
+        let err = api
+            .get_as_df(query, start_block, end_block)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DatasourceError::UnsupportedQuery { .. }));
+        assert_eq!(server.stats().query_requests, 0);
     }
 }
@@ -0,0 +1,280 @@
+use crate::stop::StopReason;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::fmt;
+use std::path::PathBuf;
+
+/// An inclusive range of blocks a request covered. `to` is `None` for open-ended requests,
+/// which let the worker decide where the batch ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockRange {
+    pub from: u64,
+    pub to: Option<u64>,
+}
+
+impl BlockRange {
+    /// A range from `from` up to and including `to`.
+    pub fn new(from: u64, to: u64) -> Self {
+        Self { from, to: Some(to) }
+    }
+
+    /// An open-ended range starting at `from`.
+    pub fn starting_at(from: u64) -> Self {
+        Self { from, to: None }
+    }
+
+    /// The range a worker query covers, read from its `fromBlock` and `toBlock`.
+    pub fn of_query(query: &Value) -> Option<Self> {
+        Some(Self {
+            from: query.get("fromBlock")?.as_u64()?,
+            to: query.get("toBlock").and_then(Value::as_u64),
+        })
+    }
+}
+
+impl fmt::Display for BlockRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to {
+            Some(to) => write!(f, "{}..={}", self.from, to),
+            None => write!(f, "{}..", self.from),
+        }
+    }
+}
+
+/// Errors returned by `Datasource`.
+///
+/// Variants carry the URL that was requested and, where known, the blocks the request covered,
+/// so failures can be logged and retried precisely.
+#[derive(Debug, thiserror::Error)]
+pub enum DatasourceError {
+    /// The request could not be sent or its body could not be read.
+    #[error("request to {url}{} failed: {source}", for_blocks(.range))]
+    Request {
+        url: String,
+        range: Option<BlockRange>,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// The router or a worker answered with a non-success status.
+    #[error("HTTP {status} from {url}{}: {body}", for_blocks(.range))]
+    Http {
+        status: StatusCode,
+        body: String,
+        url: String,
+        range: Option<BlockRange>,
+    },
+    /// The archive or RPC node answered with valid JSON that is not the expected data, usually an
+    /// error object.
+    #[error("archive error from {url}{}: {payload}", for_blocks(.range))]
+    ArchiveError {
+        payload: Value,
+        url: String,
+        range: Option<BlockRange>,
+    },
+    /// A JSON-RPC node answered with an error object.
+    #[error("JSON-RPC error {code} from {url}{}: {message}", for_blocks(.range))]
+    Rpc {
+        code: i64,
+        message: String,
+        url: String,
+        range: Option<BlockRange>,
+    },
+    /// The response body is not valid JSON or is missing required fields.
+    #[error("failed to decode response from {url}{}: {source}", for_blocks(.range))]
+    Decode {
+        url: String,
+        range: Option<BlockRange>,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// The router could not assign a worker to the block.
+    #[error("no worker available for block {block} from {url}: HTTP {status}: {body}")]
+    WorkerUnavailable {
+        block: u64,
+        url: String,
+        status: StatusCode,
+        body: String,
+    },
+    /// The requested blocks are not in the archive yet.
+    #[error("blocks {range} are beyond the dataset height {height}")]
+    RangeBeyondHeight { range: BlockRange, height: u64 },
+    /// The query asks for data the source cannot serve.
+    #[error("unsupported query: {reason}")]
+    UnsupportedQuery { reason: String },
+    /// The blocks could not be converted to a DataFrame.
+    #[error("failed to convert blocks{} to a DataFrame: {source}", for_blocks(.range))]
+    Conversion {
+        range: Option<BlockRange>,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// The HTTP client could not be built from the configured settings.
+    #[error("invalid HTTP client configuration: {source}")]
+    ClientConfig {
+        #[source]
+        source: anyhow::Error,
+    },
+    /// The checkpoint file could not be read or written.
+    #[error("checkpoint {}: {source}", .path.display())]
+    Checkpoint {
+        path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// The checkpoint was written for a different query, so resuming from it would skip blocks.
+    #[error(
+        "checkpoint {} was written for query {found}, not {expected}",
+        .path.display()
+    )]
+    CheckpointMismatch {
+        path: PathBuf,
+        expected: String,
+        found: String,
+    },
+    /// A time range fetch was stopped before its time range was resolved to blocks, so there is
+    /// no block to resume from; the fetch has to be started again.
+    #[error("stopped ({reason:?}) before the time range was resolved to blocks")]
+    TimeRangeUnresolved { reason: StopReason },
+}
+
+/// JSON-RPC error codes worth retrying: `-32007` request rate limited, which providers return
+/// when throttling, and `-32603` internal error.
+const RETRYABLE_RPC_CODES: [i64; 2] = [-32007, -32603];
+
+/// The JSON-RPC "limit exceeded" code. Providers return it both when throttling and when an
+/// `eth_getLogs` call matches too many logs; only the first is worth retrying unchanged.
+pub(crate) const RPC_LIMIT_EXCEEDED: i64 = -32005;
+
+/// Returns true if a JSON-RPC error message says the request was throttled.
+fn is_rate_limit_message(message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+    ["rate limit", "rate exceeded", "too many requests"]
+        .iter()
+        .any(|phrase| message.contains(phrase))
+}
+
+fn for_blocks(range: &Option<BlockRange>) -> String {
+    range
+        .map(|range| format!(" for blocks {}", range))
+        .unwrap_or_default()
+}
+
+impl DatasourceError {
+    /// Returns true if the error is transient and the request is worth retrying.
+    ///
+    /// Timeouts, connection failures, truncated bodies, `429 Too Many Requests`, `5xx` responses
+    /// and JSON-RPC rate limit and internal errors are retryable; any other status, error payload
+    /// or malformed response is fatal.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Request { source, .. } => {
+                source.is_timeout()
+                    || source.is_connect()
+                    || source.is_request()
+                    || source.is_body()
+            }
+            Self::Http { status, .. } | Self::WorkerUnavailable { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            Self::Decode { source, .. } => source.is_eof(),
+            Self::Rpc { code, message, .. } => {
+                RETRYABLE_RPC_CODES.contains(code)
+                    || (*code == RPC_LIMIT_EXCEEDED && is_rate_limit_message(message))
+            }
+            Self::ArchiveError { .. }
+            | Self::RangeBeyondHeight { .. }
+            | Self::UnsupportedQuery { .. }
+            | Self::Conversion { .. }
+            | Self::ClientConfig { .. }
+            | Self::Checkpoint { .. }
+            | Self::CheckpointMismatch { .. }
+            | Self::TimeRangeUnresolved { .. } => false,
+        }
+    }
+
+    /// The URL of the request that failed, if the error came from a request.
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            Self::Request { url, .. }
+            | Self::Http { url, .. }
+            | Self::ArchiveError { url, .. }
+            | Self::Decode { url, .. }
+            | Self::Rpc { url, .. }
+            | Self::WorkerUnavailable { url, .. } => Some(url),
+            Self::RangeBeyondHeight { .. }
+            | Self::UnsupportedQuery { .. }
+            | Self::Conversion { .. }
+            | Self::ClientConfig { .. }
+            | Self::Checkpoint { .. }
+            | Self::CheckpointMismatch { .. }
+            | Self::TimeRangeUnresolved { .. } => None,
+        }
+    }
+
+    /// The blocks the failed operation covered, if known.
+    pub fn range(&self) -> Option<BlockRange> {
+        match self {
+            Self::Request { range, .. }
+            | Self::Http { range, .. }
+            | Self::ArchiveError { range, .. }
+            | Self::Decode { range, .. }
+            | Self::Rpc { range, .. }
+            | Self::Conversion { range, .. } => *range,
+            Self::WorkerUnavailable { block, .. } => Some(BlockRange::starting_at(*block)),
+            Self::RangeBeyondHeight { range, .. } => Some(*range),
+            Self::UnsupportedQuery { .. }
+            | Self::ClientConfig { .. }
+            | Self::Checkpoint { .. }
+            | Self::CheckpointMismatch { .. }
+            | Self::TimeRangeUnresolved { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORKER_URL: &str = "https://worker.example/query";
+
+    fn http(status: StatusCode) -> DatasourceError {
+        DatasourceError::Http {
+            status,
+            body: String::new(),
+            url: WORKER_URL.to_string(),
+            range: Some(BlockRange::starting_at(100)),
+        }
+    }
+
+    fn decode(body: &str) -> DatasourceError {
+        DatasourceError::Decode {
+            url: WORKER_URL.to_string(),
+            range: None,
+            source: serde_json::from_str::<Value>(body).unwrap_err(),
+        }
+    }
+
+    #[test]
+    fn test_http_status_classification() {
+        assert!(http(StatusCode::SERVICE_UNAVAILABLE).is_retryable());
+        assert!(http(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!http(StatusCode::BAD_REQUEST).is_retryable());
+    }
+
+    #[test]
+    fn test_truncated_body_is_retryable() {
+        assert!(decode("[{\"header\": {").is_retryable());
+        assert!(!decode("not json").is_retryable());
+    }
+
+    #[test]
+    fn test_error_carries_context() {
+        let error = http(StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.url(), Some(WORKER_URL));
+        assert_eq!(error.range(), Some(BlockRange::starting_at(100)));
+        assert_eq!(
+            error.to_string(),
+            "HTTP 503 Service Unavailable from https://worker.example/query for blocks 100..: "
+        );
+    }
+}
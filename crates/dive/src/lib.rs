@@ -1,4 +1,23 @@
 //pub mod datalake;
+pub mod block_time;
+pub mod cache;
+pub mod cassette;
+pub mod checkpoint;
 pub mod datasource;
+pub mod error;
+pub mod http;
+#[cfg(any(test, feature = "mock-archive"))]
+pub mod mock_archive;
+pub mod network;
+pub mod planner;
+pub mod progress;
 pub mod query_builder;
+pub mod rate_limit;
+pub mod retry;
+pub mod rpc;
+pub mod source;
+pub mod stop;
+pub mod telemetry;
+pub mod transport;
 pub mod utils;
+pub mod workers;
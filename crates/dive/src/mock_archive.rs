@@ -0,0 +1,870 @@
+//! An in-process HTTP server speaking the archive protocol `Datasource` uses, for testing
+//! without the network.
+//!
+//! The server answers `GET /height`, `GET /{block}/worker` and worker query `POST`s from blocks
+//! loaded from JSON fixtures. Each worker serves a fixed block range and each response covers at
+//! most a fixed number of blocks, so pagination and worker rotation behave like the real archive.
+//! Latency and failures can be injected to exercise retries and concurrency deterministically.
+//!
+//! Available in tests and with the `mock-archive` feature.
+
+use anyhow::{Context, Error};
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// The endpoints of the mock archive, used to target injected failures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Height,
+    Worker,
+    Query,
+}
+
+/// A failure the mock archive injects instead of a normal response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// Respond with this status code and body.
+    Status(u16, String),
+    /// Respond `200 OK` with the first half of the JSON body.
+    TruncatedBody,
+    /// Close the connection without responding.
+    Disconnect,
+}
+
+/// Counters of the requests the mock archive has served.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MockArchiveStats {
+    pub height_requests: usize,
+    pub worker_requests: usize,
+    pub query_requests: usize,
+    /// The most worker queries that were being handled at the same time.
+    pub max_concurrent_queries: usize,
+}
+
+/// Blocks and behaviour for a mock archive server.
+///
+/// # Examples
+///
+/// no_run
+/// let server = MockArchive::from_fixture("tests/fixtures/blocks.json")?
+///     .with_worker_range_size(10)
+///     .with_max_blocks_per_response(4)
+///     .with_failure(Endpoint::Query, Failure::Status(503, "busy".to_string()))
+///     .start()
+///     .await?;
+/// let datasource = Datasource::new(DatasourceConfig::new(server.url(), 10));
+///
+#[derive(Clone, Debug)]
+pub struct MockArchive {
+    blocks: Vec<Value>,
+    height: Option<u64>,
+    worker_range_size: u64,
+    replicas: u64,
+    max_blocks_per_response: u64,
+    latency: Duration,
+    failures: Vec<(Endpoint, Failure)>,
+}
+
+impl MockArchive {
+    /// Creates a mock archive serving `blocks`, which are in the archive JSON shape.
+    pub fn new(mut blocks: Vec<Value>) -> Self {
+        blocks.sort_by_key(block_number);
+        Self {
+            blocks,
+            height: None,
+            worker_range_size: 1_000,
+            replicas: 1,
+            max_blocks_per_response: 100,
+            latency: Duration::ZERO,
+            failures: Vec::new(),
+        }
+    }
+
+    /// Loads blocks from a JSON file holding an array of blocks.
+    pub fn from_fixture(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self::new(read_fixture(path.as_ref())?))
+    }
+
+    /// Loads blocks from every `.json` file in a directory.
+    pub fn from_fixture_dir(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut blocks = Vec::new();
+        for entry in std::fs::read_dir(dir.as_ref())? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                blocks.extend(read_fixture(&path)?);
+            }
+        }
+        Ok(Self::new(blocks))
+    }
+
+    /// Reports `height` from `/height` and serves no blocks above it. Defaults to the last
+    /// fixture block.
+    pub fn with_height(mut self, height: u64) -> Self {
+        self.height = Some(height);
+        self
+    }
+
+    /// Makes each worker serve `size` consecutive block numbers, starting from block 0.
+    pub fn with_worker_range_size(mut self, size: u64) -> Self {
+        self.worker_range_size = size.max(1);
+        self
+    }
+
+    /// Serves each worker range from `replicas` workers with their own URLs. The router names
+    /// them in turn, so asking twice for the same block can give two different URLs.
+    pub fn with_replicas(mut self, replicas: u64) -> Self {
+        self.replicas = replicas.max(1);
+        self
+    }
+
+    /// Makes each worker response cover at most `blocks` consecutive block numbers.
+    pub fn with_max_blocks_per_response(mut self, blocks: u64) -> Self {
+        self.max_blocks_per_response = blocks.max(1);
+        self
+    }
+
+    /// Delays every response by `latency`.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Makes the next request to `endpoint` fail. Failures queued for the same endpoint are
+    /// used in order, one per request.
+    pub fn with_failure(mut self, endpoint: Endpoint, failure: Failure) -> Self {
+        self.failures.push((endpoint, failure));
+        self
+    }
+
+    /// Binds to a free local port and starts serving in the background.
+    pub async fn start(self) -> Result<MockArchiveServer, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(ServerState {
+            url: format!("http://{}", addr),
+            failures: Mutex::new(self.failures.iter().cloned().collect()),
+            archive: self,
+            stats: Mutex::new(MockArchiveStats::default()),
+            queries_in_flight: AtomicUsize::new(0),
+            router_requests: AtomicU64::new(0),
+        });
+
+        let server_state = state.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    // A client hanging up early is not a server error.
+                    let _ = state.handle_connection(stream).await;
+                });
+            }
+        });
+
+        Ok(MockArchiveServer {
+            addr,
+            state,
+            handle,
+        })
+    }
+
+    fn height(&self) -> u64 {
+        self.height
+            .or_else(|| {
+                self.blocks
+                    .last()
+                    .and_then(|b| b["header"]["number"].as_u64())
+            })
+            .unwrap_or(0)
+    }
+
+    fn worker_range(&self, index: u64) -> (u64, u64) {
+        let start = index * self.worker_range_size;
+        (start, start + self.worker_range_size - 1)
+    }
+
+    /// Answers a worker query the way an archive worker would.
+    fn query(&self, worker: u64, query: &Value) -> Result<Value, (u16, String)> {
+        let from_block = query
+            .get("fromBlock")
+            .and_then(Value::as_u64)
+            .ok_or((400, "fromBlock is required".to_string()))?;
+        let (worker_start, worker_end) = self.worker_range(worker);
+        if from_block < worker_start || from_block > worker_end {
+            return Err((
+                400,
+                format!("block {} is not in the range of this worker", from_block),
+            ));
+        }
+        let height = self.height();
+        if from_block > height {
+            return Err((400, format!("block {} is beyond the height", from_block)));
+        }
+
+        let mut last_block = worker_end
+            .min(height)
+            .min(from_block + self.max_blocks_per_response - 1);
+        if let Some(to_block) = query.get("toBlock").and_then(Value::as_u64) {
+            last_block = last_block.min(to_block);
+        }
+
+        let include_all = query["includeAllBlocks"].as_bool().unwrap_or(false)
+            || query.get("blocks").is_some()
+            || !["logs", "transactions", "traces"]
+                .iter()
+                .any(|key| query.get(*key).is_some());
+
+        let scanned: Vec<&Value> = self
+            .blocks
+            .iter()
+            .filter(|block| {
+                let number = block_number(block);
+                number >= from_block && number <= last_block
+            })
+            .collect();
+        let mut response = Vec::new();
+        for (i, block) in scanned.iter().enumerate() {
+            let selected = select_block(block, query);
+            let has_items = ["logs", "transactions", "traces"].iter().any(|key| {
+                selected[key]
+                    .as_array()
+                    .is_some_and(|items| !items.is_empty())
+            });
+            // The last scanned block is always returned so clients know where the batch ended.
+            if include_all || has_items || i == scanned.len() - 1 {
+                response.push(selected);
+            }
+        }
+        Ok(Value::Array(response))
+    }
+}
+
+/// A running mock archive. The server stops when this is dropped.
+pub struct MockArchiveServer {
+    addr: SocketAddr,
+    state: Arc<ServerState>,
+    handle: JoinHandle<()>,
+}
+
+impl MockArchiveServer {
+    /// The base URL to use as `DatasourceConfig::base_url`.
+    pub fn url(&self) -> String {
+        self.state.url.clone()
+    }
+
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The block range served by the worker with this URL, if it is one of this server's workers.
+    pub fn worker_range(&self, worker_url: &str) -> Option<(u64, u64)> {
+        let path = worker_url.strip_prefix(&self.state.url)?;
+        Some(self.state.archive.worker_range(worker_index(path)?))
+    }
+
+    /// Queues another failure for `endpoint`.
+    pub fn inject_failure(&self, endpoint: Endpoint, failure: Failure) {
+        self.state
+            .failures
+            .lock()
+            .unwrap()
+            .push_back((endpoint, failure));
+    }
+
+    /// Returns the request counters so far.
+    pub fn stats(&self) -> MockArchiveStats {
+        self.state.stats.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockArchiveServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+struct ServerState {
+    url: String,
+    archive: MockArchive,
+    failures: Mutex<VecDeque<(Endpoint, Failure)>>,
+    stats: Mutex<MockArchiveStats>,
+    queries_in_flight: AtomicUsize,
+    router_requests: AtomicU64,
+}
+
+/// A parsed HTTP request.
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) body: Vec<u8>,
+}
+
+pub(crate) enum Response {
+    Ok(String),
+    Error(u16, String),
+    Truncated(String),
+    Disconnect,
+}
+
+impl ServerState {
+    async fn handle_connection(&self, mut stream: TcpStream) -> Result<(), Error> {
+        let request = read_request(&mut stream).await?;
+        let endpoint = route(&request);
+        if endpoint == Some(Endpoint::Query) {
+            let in_flight = self.queries_in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            let mut stats = self.stats.lock().unwrap();
+            stats.max_concurrent_queries = stats.max_concurrent_queries.max(in_flight);
+        }
+
+        tokio::time::sleep(self.archive.latency).await;
+        let response = self.respond(endpoint, &request);
+
+        if endpoint == Some(Endpoint::Query) {
+            self.queries_in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+        write_response(&mut stream, response).await
+    }
+
+    fn respond(&self, endpoint: Option<Endpoint>, request: &Request) -> Response {
+        let Some(endpoint) = endpoint else {
+            return Response::Error(404, format!("no route for {}", request.path));
+        };
+        {
+            let mut stats = self.stats.lock().unwrap();
+            match endpoint {
+                Endpoint::Height => stats.height_requests += 1,
+                Endpoint::Worker => stats.worker_requests += 1,
+                Endpoint::Query => stats.query_requests += 1,
+            }
+        }
+
+        let response = match endpoint {
+            Endpoint::Height => Response::Ok(self.archive.height().to_string()),
+            Endpoint::Worker => self.worker_url(&request.path),
+            Endpoint::Query => self.query(request),
+        };
+        match (self.take_failure(endpoint), response) {
+            (None, response) => response,
+            (Some(Failure::Status(status, body)), _) => Response::Error(status, body),
+            (Some(Failure::TruncatedBody), Response::Ok(body)) => Response::Truncated(body),
+            (Some(Failure::TruncatedBody), response) => response,
+            (Some(Failure::Disconnect), _) => Response::Disconnect,
+        }
+    }
+
+    fn take_failure(&self, endpoint: Endpoint) -> Option<Failure> {
+        let mut failures = self.failures.lock().unwrap();
+        let position = failures.iter().position(|(e, _)| *e == endpoint)?;
+        failures.remove(position).map(|(_, failure)| failure)
+    }
+
+    fn worker_url(&self, path: &str) -> Response {
+        let block: Option<u64> = path
+            .trim_start_matches('/')
+            .strip_suffix("/worker")
+            .and_then(|block| block.parse().ok());
+        match block {
+            Some(block) if block <= self.archive.height() => {
+                let index = block / self.archive.worker_range_size;
+                let replica =
+                    self.router_requests.fetch_add(1, Ordering::SeqCst) % self.archive.replicas;
+                if replica == 0 {
+                    Response::Ok(format!("{}/worker/{}", self.url, index))
+                } else {
+                    Response::Ok(format!("{}/worker/{}/{}", self.url, index, replica))
+                }
+            }
+            Some(block) => Response::Error(503, format!("block {} is not ready yet", block)),
+            None => Response::Error(400, format!("invalid block in {}", path)),
+        }
+    }
+
+    fn query(&self, request: &Request) -> Response {
+        let worker = worker_index(&request.path);
+        let query = serde_json::from_slice::<Value>(&request.body);
+        match (worker, query) {
+            (Some(worker), Ok(query)) => match self.archive.query(worker, &query) {
+                Ok(blocks) => Response::Ok(blocks.to_string()),
+                Err((status, message)) => Response::Error(status, message),
+            },
+            (None, _) => Response::Error(404, format!("no worker at {}", request.path)),
+            (_, Err(e)) => Response::Error(400, format!("invalid query: {}", e)),
+        }
+    }
+}
+
+fn route(request: &Request) -> Option<Endpoint> {
+    match request.method.as_str() {
+        "GET" if request.path == "/height" => Some(Endpoint::Height),
+        "GET" if request.path.ends_with("/worker") => Some(Endpoint::Worker),
+        "POST" if request.path.starts_with("/worker/") => Some(Endpoint::Query),
+        _ => None,
+    }
+}
+
+/// Parses the worker index from a worker path, `/worker/{index}` or `/worker/{index}/{replica}`.
+fn worker_index(path: &str) -> Option<u64> {
+    let mut segments = path.strip_prefix("/worker/")?.split('/');
+    let index = segments.next()?.parse().ok()?;
+    match (segments.next(), segments.next()) {
+        (None, _) => Some(index),
+        (Some(replica), None) if replica.parse::<u64>().is_ok() => Some(index),
+        _ => None,
+    }
+}
+
+pub(crate) async fn read_request(stream: &mut TcpStream) -> Result<Request, Error> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            anyhow::bail!("connection closed before the request headers");
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(position) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break position + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().context("empty request")?.split_whitespace();
+    let method = request_line.next().context("missing method")?.to_string();
+    let path = request_line.next().context("missing path")?.to_string();
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let mut body = buffer[header_end..].to_vec();
+    while body.len() < content_length {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            anyhow::bail!("connection closed before the request body");
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(content_length);
+
+    Ok(Request { method, path, body })
+}
+
+pub(crate) async fn write_response(
+    stream: &mut TcpStream,
+    response: Response,
+) -> Result<(), Error> {
+    let (status, body) = match response {
+        Response::Ok(body) => (200, body),
+        Response::Error(status, body) => (status, body),
+        Response::Truncated(body) => (200, body[..body.len() / 2].to_string()),
+        Response::Disconnect => return Ok(()),
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+fn read_fixture(path: &Path) -> Result<Vec<Value>, Error> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("reading fixture {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("parsing fixture {}", path.display()))
+}
+
+fn block_number(block: &Value) -> u64 {
+    block["header"]["number"].as_u64().unwrap_or(0)
+}
+
+/// Applies the query's item filters and field selection to a fixture block.
+fn select_block(block: &Value, query: &Value) -> Value {
+    let fields = &query["fields"];
+    let mut selected = Map::new();
+
+    let mut header = select_fields(&block["header"], &fields["block"]);
+    for always in ["number", "hash"] {
+        if let Some(value) = block["header"].get(always) {
+            header.insert(always.to_string(), value.clone());
+        }
+    }
+    selected.insert("header".to_string(), Value::Object(header));
+
+    let datasets = [
+        ("logs", "log", &["logIndex", "transactionIndex"][..]),
+        ("transactions", "transaction", &["transactionIndex"][..]),
+        ("traces", "trace", &["transactionIndex", "traceAddress"][..]),
+    ];
+    for (key, field_key, always) in datasets {
+        let Some(requests) = query.get(key).and_then(Value::as_array) else {
+            continue;
+        };
+        let items: Vec<Value> = block[key]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|item| requests.iter().any(|request| matches(key, item, request)))
+            .map(|item| {
+                let mut item_fields = select_fields(item, &fields[field_key]);
+                for field in always {
+                    if let Some(value) = item.get(*field) {
+                        item_fields.insert(field.to_string(), value.clone());
+                    }
+                }
+                Value::Object(item_fields)
+            })
+            .collect();
+        selected.insert(key.to_string(), Value::Array(items));
+    }
+    Value::Object(selected)
+}
+
+fn select_fields(item: &Value, selection: &Value) -> Map<String, Value> {
+    let mut fields = Map::new();
+    if let Some(selection) = selection.as_object() {
+        for (name, wanted) in selection {
+            if wanted.as_bool() == Some(true) {
+                if let Some(value) = item.get(name) {
+                    fields.insert(name.clone(), value.clone());
+                }
+            }
+        }
+    }
+    fields
+}
+
+/// Returns true if an item passes every filter in a log, transaction or trace request.
+fn matches(dataset: &str, item: &Value, request: &Value) -> bool {
+    let Some(filters) = request.as_object() else {
+        return true;
+    };
+    filters.iter().all(|(name, allowed)| {
+        let Some(allowed) = allowed.as_array() else {
+            // Flags such as `"transaction": true` are relations, not filters.
+            return true;
+        };
+        let actual = match (dataset, name.as_str()) {
+            ("logs", "address") => item["address"].as_str(),
+            ("logs", topic) if topic.starts_with("topic") => topic[5..]
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| item["topics"].get(i))
+                .and_then(Value::as_str),
+            ("transactions", "from") => item["from"].as_str(),
+            ("transactions", "to") => item["to"].as_str(),
+            ("transactions", "sighash") => item["input"].as_str().and_then(|input| input.get(..10)),
+            ("traces", "type") => item["type"].as_str(),
+            _ => return true,
+        };
+        actual.is_some_and(|actual| {
+            allowed
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|allowed| allowed.eq_ignore_ascii_case(actual))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasource::{Datasource, DatasourceConfig};
+    use crate::error::DatasourceError;
+    use crate::retry::RetryPolicy;
+    use serde_json::json;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/blocks.json");
+    const USDC: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+
+    fn datasource(server: &MockArchiveServer, max_concurrent_requests: usize) -> Datasource {
+        let config = DatasourceConfig::new(server.url(), max_concurrent_requests)
+            .with_retry_policy(RetryPolicy {
+                base_delay: Duration::ZERO,
+                ..Default::default()
+            });
+        Datasource::new(config)
+    }
+
+    fn usdc_query() -> Value {
+        json!({
+            "logs": [{"address": [USDC]}],
+            "fields": {"log": {"address": true, "topics": true, "data": true}},
+        })
+    }
+
+    #[tokio::test]
+    async fn test_pagination_across_workers() {
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_worker_range_size(10)
+            .with_max_blocks_per_response(4)
+            .start()
+            .await
+            .unwrap();
+        let api = datasource(&server, 10);
+
+        let data = api
+            .get_data_in_range(json!({"includeAllBlocks": true}), 14000000, 14000029)
+            .await
+            .unwrap();
+        let numbers: Vec<u64> = data.iter().map(block_number).collect();
+        assert_eq!(numbers, (14000000..=14000029).collect::<Vec<_>>());
+
+        // Each worker range of 10 blocks takes 3 responses of at most 4 blocks. Workers are reused
+        // until one turns the next block away, which happens once at each of the 2 range ends.
+        let stats = server.stats();
+        assert_eq!(stats.query_requests, 9 + 2);
+        assert_eq!(stats.worker_requests, 3);
+    }
+
+    #[tokio::test]
+    async fn test_chunks_aligned_to_worker_ranges() {
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_worker_range_size(7)
+            .start()
+            .await
+            .unwrap();
+        let config = DatasourceConfig::new(server.url(), 4).with_worker_aligned_chunks(true);
+        let api = Datasource::new(config);
+
+        let ranges = api
+            .discover_worker_ranges(14000000, 14000029)
+            .await
+            .unwrap();
+        let bounds: Vec<(u64, u64)> = ranges
+            .iter()
+            .map(|range| (range.start_block, range.end_block))
+            .collect();
+        assert_eq!(
+            bounds,
+            vec![
+                (14000000, 14000006),
+                (14000007, 14000013),
+                (14000014, 14000020),
+                (14000021, 14000027),
+                (14000028, 14000029),
+            ]
+        );
+        assert_eq!(
+            server.worker_range(&ranges[1].worker_url),
+            Some((14000007, 14000013))
+        );
+        // The router is asked once per range; the range ends come from the workers.
+        let discovered = server.stats();
+        assert_eq!(discovered.worker_requests, 5);
+
+        // Discovered ranges are remembered and each chunk is a single request to its worker.
+        let frames = api
+            .get_parallelel_chunks(usdc_query(), 14000000, 14000029, 10)
+            .await
+            .unwrap();
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames.iter().map(|df| df.height()).sum::<usize>(), 27);
+        let stats = server.stats();
+        assert_eq!(stats.worker_requests, discovered.worker_requests);
+        assert_eq!(stats.query_requests, discovered.query_requests + 5);
+    }
+
+    #[tokio::test]
+    async fn test_worker_range_discovery_returns_client_errors() {
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_worker_range_size(7)
+            .with_failure(
+                Endpoint::Query,
+                Failure::Status(403, "forbidden".to_string()),
+            )
+            .start()
+            .await
+            .unwrap();
+        let api = Datasource::new(DatasourceConfig::new(server.url(), 4));
+
+        let error = api
+            .discover_worker_ranges(14000000, 14000029)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            DatasourceError::Http { status, .. } if status.as_u16() == 403
+        ));
+        assert_eq!(server.stats().query_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_worker_ranges_with_replicas() {
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_worker_range_size(7)
+            .with_replicas(2)
+            .start()
+            .await
+            .unwrap();
+        let api = datasource(&server, 4);
+
+        // The router alternates between two replica URLs for every range, so URLs differ within
+        // a range, but the boundaries are where the workers' ranges end.
+        let ranges = api
+            .discover_worker_ranges(14000000, 14000029)
+            .await
+            .unwrap();
+        let bounds: Vec<(u64, u64)> = ranges
+            .iter()
+            .map(|range| (range.start_block, range.end_block))
+            .collect();
+        assert_eq!(
+            bounds,
+            vec![
+                (14000000, 14000006),
+                (14000007, 14000013),
+                (14000014, 14000020),
+                (14000021, 14000027),
+                (14000028, 14000029),
+            ]
+        );
+        for range in &ranges {
+            assert_eq!(
+                server
+                    .worker_range(&range.worker_url)
+                    .map(|(start, _)| start),
+                Some(range.start_block)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clipped_worker_range_is_extended() {
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_worker_range_size(7)
+            .start()
+            .await
+            .unwrap();
+        let api = datasource(&server, 4);
+
+        let ranges = api
+            .discover_worker_ranges(14000000, 14000010)
+            .await
+            .unwrap();
+        assert_eq!(ranges.last().unwrap().end_block, 14000010);
+
+        // The range cut short at 14000010 is extended to the worker's real end, not split there.
+        let ranges = api
+            .discover_worker_ranges(14000000, 14000020)
+            .await
+            .unwrap();
+        let bounds: Vec<(u64, u64)> = ranges
+            .iter()
+            .map(|range| (range.start_block, range.end_block))
+            .collect();
+        assert_eq!(
+            bounds,
+            vec![
+                (14000000, 14000006),
+                (14000007, 14000013),
+                (14000014, 14000020)
+            ]
+        );
+        assert_eq!(server.stats().worker_requests, 3);
+    }
+
+    #[tokio::test]
+    async fn test_filters_and_field_selection() {
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+        let api = datasource(&server, 10);
+
+        let data = api
+            .get_data_in_range(usdc_query(), 14000000, 14000029)
+            .await
+            .unwrap();
+        let logs: Vec<&Value> = data
+            .iter()
+            .flat_map(|block| block["logs"].as_array().unwrap())
+            .collect();
+        assert!(!logs.is_empty());
+        assert!(logs.iter().all(|log| log["address"] == USDC));
+        assert!(logs.iter().all(|log| log.get("transactionHash").is_none()));
+        assert!(data.iter().all(|block| block.get("transactions").is_none()));
+
+        let df = api
+            .get_as_df(usdc_query(), 14000000, 14000029)
+            .await
+            .unwrap();
+        assert_eq!(df.height(), logs.len());
+    }
+
+    #[tokio::test]
+    async fn test_injected_failures_are_retried() {
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_failure(Endpoint::Worker, Failure::Status(503, "busy".to_string()))
+            .with_failure(
+                Endpoint::Query,
+                Failure::Status(429, "slow down".to_string()),
+            )
+            .with_failure(Endpoint::Query, Failure::TruncatedBody)
+            .with_failure(Endpoint::Query, Failure::Disconnect)
+            .start()
+            .await
+            .unwrap();
+        let api = datasource(&server, 10);
+
+        let data = api
+            .get_data_in_range(json!({"includeAllBlocks": true}), 14000000, 14000009)
+            .await
+            .unwrap();
+        assert_eq!(data.len(), 10);
+        assert_eq!(server.stats().query_requests, 4);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_is_bounded() {
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_max_blocks_per_response(2)
+            .with_latency(Duration::from_millis(20))
+            .start()
+            .await
+            .unwrap();
+        let api = datasource(&server, 2);
+
+        let dfs = api
+            .get_parallelel_chunks(usdc_query(), 14000000, 14000029, 5)
+            .await
+            .unwrap();
+        assert_eq!(dfs.len(), 6);
+        assert_eq!(server.stats().max_concurrent_queries, 2);
+    }
+}
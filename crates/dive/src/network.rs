@@ -0,0 +1,241 @@
+//! Known archive endpoints and chain metadata.
+//!
+//! `Network` lists the EVM chains served by the public Subsquid archive. A `NetworkRegistry`
+//! holds their `NetworkInfo` and can be extended with custom networks, for example private
+//! gateways, loaded from a JSON file.
+
+use anyhow::{Context, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+const ARCHIVE_URL: &str = "https://v2.archive.subsquid.io/network";
+
+/// EVM networks available on the public Subsquid archive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Network {
+    Ethereum,
+    Arbitrum,
+    ArbitrumNova,
+    Optimism,
+    Base,
+    Polygon,
+    Bsc,
+    Avalanche,
+    Gnosis,
+    Fantom,
+    ZkSync,
+    Linea,
+    Scroll,
+    Blast,
+    Moonbeam,
+}
+
+impl Network {
+    /// Every built-in network.
+    pub const ALL: [Network; 15] = [
+        Self::Ethereum,
+        Self::Arbitrum,
+        Self::ArbitrumNova,
+        Self::Optimism,
+        Self::Base,
+        Self::Polygon,
+        Self::Bsc,
+        Self::Avalanche,
+        Self::Gnosis,
+        Self::Fantom,
+        Self::ZkSync,
+        Self::Linea,
+        Self::Scroll,
+        Self::Blast,
+        Self::Moonbeam,
+    ];
+
+    /// The archive's name for the network, the last segment of its URL.
+    pub fn name(self) -> &'static str {
+        self.spec().0
+    }
+
+    /// The EIP-155 chain id.
+    pub fn chain_id(self) -> u64 {
+        self.spec().1
+    }
+
+    /// The archive URL to use as `DatasourceConfig::base_url`.
+    pub fn archive_url(self) -> String {
+        format!("{}/{}", ARCHIVE_URL, self.name())
+    }
+
+    /// Looks up a built-in network by its chain id.
+    pub fn from_chain_id(chain_id: u64) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|network| network.chain_id() == chain_id)
+    }
+
+    /// Returns the network's name, chain id and target block time in milliseconds. All built-in
+    /// networks have a native token with 18 decimals.
+    fn spec(self) -> (&'static str, u64, u64) {
+        match self {
+            Self::Ethereum => ("ethereum-mainnet", 1, 12_000),
+            Self::Arbitrum => ("arbitrum-one", 42161, 250),
+            Self::ArbitrumNova => ("arbitrum-nova", 42170, 250),
+            Self::Optimism => ("optimism-mainnet", 10, 2_000),
+            Self::Base => ("base-mainnet", 8453, 2_000),
+            Self::Polygon => ("polygon-mainnet", 137, 2_000),
+            Self::Bsc => ("binance-mainnet", 56, 3_000),
+            Self::Avalanche => ("avalanche-mainnet", 43114, 2_000),
+            Self::Gnosis => ("gnosis-mainnet", 100, 5_000),
+            Self::Fantom => ("fantom-mainnet", 250, 1_000),
+            Self::ZkSync => ("zksync-mainnet", 324, 1_000),
+            Self::Linea => ("linea-mainnet", 59144, 2_000),
+            Self::Scroll => ("scroll-mainnet", 534352, 3_000),
+            Self::Blast => ("blast-l2-mainnet", 81457, 2_000),
+            Self::Moonbeam => ("moonbeam-mainnet", 1284, 6_000),
+        }
+    }
+}
+
+/// Archive endpoint and chain metadata of a network.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkInfo {
+    pub name: String,
+    pub archive_url: String,
+    pub chain_id: u64,
+    pub native_decimals: u8,
+    pub block_time_ms: u64,
+}
+
+impl NetworkInfo {
+    /// The target time between blocks.
+    pub fn block_time(&self) -> Duration {
+        Duration::from_millis(self.block_time_ms)
+    }
+}
+
+impl From<Network> for NetworkInfo {
+    fn from(network: Network) -> Self {
+        let (name, chain_id, block_time_ms) = network.spec();
+        Self {
+            name: name.to_string(),
+            archive_url: network.archive_url(),
+            chain_id,
+            native_decimals: 18,
+            block_time_ms,
+        }
+    }
+}
+
+/// Networks by name: the built-in ones plus any registered at runtime.
+///
+/// # Examples
+///
+/// no_run
+/// let registry = NetworkRegistry::with_builtins().load_file("networks.json")?;
+/// let config = DatasourceConfig::for_network(registry.get("my-devnet").unwrap().clone());
+///
+#[derive(Clone, Debug, Default)]
+pub struct NetworkRegistry {
+    networks: BTreeMap<String, NetworkInfo>,
+}
+
+impl NetworkRegistry {
+    /// Creates a registry holding every built-in `Network`.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        for network in Network::ALL {
+            registry.register(network.into());
+        }
+        registry
+    }
+
+    /// Adds a network, replacing any network registered under the same name.
+    pub fn register(&mut self, network: NetworkInfo) {
+        self.networks.insert(network.name.clone(), network);
+    }
+
+    /// Registers the networks listed in a JSON file holding an array of `NetworkInfo` objects:
+    ///
+    /// ```json
+    /// [{"name": "my-devnet", "archive_url": "https://archive.internal/my-devnet",
+    ///   "chain_id": 31337, "native_decimals": 18, "block_time_ms": 1000}]
+    /// ```
+    pub fn load_file(mut self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("reading networks from {}", path.display()))?;
+        let networks: Vec<NetworkInfo> = serde_json::from_str(&contents)
+            .with_context(|| format!("parsing networks from {}", path.display()))?;
+        for network in networks {
+            self.register(network);
+        }
+        Ok(self)
+    }
+
+    /// Returns the network registered under `name`.
+    pub fn get(&self, name: &str) -> Option<&NetworkInfo> {
+        self.networks.get(name)
+    }
+
+    /// Returns the network with the given chain id.
+    pub fn by_chain_id(&self, chain_id: u64) -> Option<&NetworkInfo> {
+        self.networks
+            .values()
+            .find(|network| network.chain_id == chain_id)
+    }
+
+    /// Iterates over all registered networks in name order.
+    pub fn iter(&self) -> impl Iterator<Item = &NetworkInfo> {
+        self.networks.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasource::DatasourceConfig;
+
+    #[test]
+    fn test_builtin_networks() {
+        let base = NetworkInfo::from(Network::Base);
+        assert_eq!(base.archive_url, format!("{}/base-mainnet", ARCHIVE_URL));
+        assert_eq!(base.chain_id, 8453);
+        assert_eq!(base.block_time(), Duration::from_secs(2));
+        assert_eq!(Network::from_chain_id(56), Some(Network::Bsc));
+
+        let config = DatasourceConfig::for_network(Network::Ethereum);
+        assert_eq!(
+            config.base_url,
+            "https://v2.archive.subsquid.io/network/ethereum-mainnet"
+        );
+    }
+
+    #[test]
+    fn test_load_custom_networks() {
+        let path = std::env::temp_dir().join(format!("dive-networks-{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"[
+                {"name": "my-devnet", "archive_url": "http://127.0.0.1:8080", "chain_id": 31337,
+                 "native_decimals": 18, "block_time_ms": 1000},
+                {"name": "base-mainnet", "archive_url": "https://gateway.internal/base",
+                 "chain_id": 8453, "native_decimals": 18, "block_time_ms": 2000}
+            ]"#,
+        )
+        .unwrap();
+        let registry = NetworkRegistry::with_builtins().load_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(registry.get("my-devnet").unwrap().chain_id, 31337);
+        assert_eq!(
+            registry.by_chain_id(8453).unwrap().archive_url,
+            "https://gateway.internal/base"
+        );
+        assert_eq!(registry.iter().count(), Network::ALL.len() + 1);
+        assert!(NetworkRegistry::default()
+            .load_file("/nonexistent/networks.json")
+            .is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! Planning of the chunks `Datasource::get_parallelel_chunks` fetches.
+//!
+//! Without a `ChunkSizing` every chunk has the `chunk_size` passed in. With one, that size is only
+//! the first guess: each completed chunk reports how many bytes and items it held per block, and
+//! the rest of the range is carved into chunks sized to hit the target. Dense stretches of the
+//! chain get split into smaller chunks and sparse ones merged into larger chunks.
+//!
+//! Given worker boundaries, no chunk crosses one, so each chunk is served by a single worker.
+
+/// How much a chunk may grow over the previous planned size in one step, so that one sparse
+/// sample does not swallow a dense stretch that follows it.
+const MAX_GROWTH: u64 = 4;
+
+/// The weight of the newest sample in the running per-block averages.
+const SMOOTHING: f64 = 0.5;
+
+/// Targets for adaptively sized chunks.
+///
+/// # Examples
+///
+/// no_run
+/// let sizing = ChunkSizing::default()
+///     .with_target_bytes(4 << 20)
+///     .with_target_items(50_000);
+/// let config = DatasourceConfig::new("https://api.example.com".to_string(), 10)
+///     .with_chunk_sizing(sizing);
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkSizing {
+    /// The response size to aim for per chunk, in bytes.
+    pub target_bytes: u64,
+    /// The number of items, such as logs or transactions, to aim for per chunk.
+    pub target_items: Option<u64>,
+    pub min_chunk_size: u64,
+    pub max_chunk_size: u64,
+}
+
+impl Default for ChunkSizing {
+    fn default() -> Self {
+        Self {
+            target_bytes: 8 << 20,
+            target_items: None,
+            min_chunk_size: 10,
+            max_chunk_size: 1_000_000,
+        }
+    }
+}
+
+impl ChunkSizing {
+    pub fn with_target_bytes(mut self, bytes: u64) -> Self {
+        self.target_bytes = bytes;
+        self
+    }
+
+    pub fn with_target_items(mut self, items: u64) -> Self {
+        self.target_items = Some(items);
+        self
+    }
+
+    /// Limits chunks to between `min` and `max` blocks.
+    pub fn with_chunk_size_limits(mut self, min: u64, max: u64) -> Self {
+        self.min_chunk_size = min;
+        self.max_chunk_size = max;
+        self
+    }
+
+    /// Returns the chunk size that hits the targets at the given densities.
+    fn chunk_size(&self, bytes_per_block: f64, items_per_block: f64, previous: u64) -> u64 {
+        let mut size = self.max_chunk_size as f64;
+        if bytes_per_block > 0.0 {
+            size = size.min(self.target_bytes as f64 / bytes_per_block);
+        }
+        if let Some(target_items) = self.target_items.filter(|_| items_per_block > 0.0) {
+            size = size.min(target_items as f64 / items_per_block);
+        }
+        (size as u64)
+            .min(previous.saturating_mul(MAX_GROWTH))
+            .min(self.max_chunk_size)
+            .max(self.min_chunk_size)
+            .max(1)
+    }
+}
+
+/// What a fetched chunk held.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct ChunkSample {
+    pub(crate) blocks: u64,
+    pub(crate) bytes: u64,
+    pub(crate) items: u64,
+}
+
+/// Carves a block range into chunks, one at a time, sized from the samples recorded so far.
+#[derive(Debug)]
+pub(crate) struct ChunkPlanner {
+    next_block: u64,
+    end_block: u64,
+    chunk_size: u64,
+    sizing: Option<ChunkSizing>,
+    /// Running averages over the samples, `None` until the first one.
+    density: Option<(f64, f64)>,
+    /// The first blocks of worker ranges, in order.
+    boundaries: Vec<u64>,
+}
+
+impl ChunkPlanner {
+    pub(crate) fn new(
+        start_block: u64,
+        end_block: u64,
+        chunk_size: u64,
+        sizing: Option<ChunkSizing>,
+    ) -> Self {
+        Self {
+            next_block: start_block,
+            end_block,
+            chunk_size: chunk_size.max(1),
+            sizing,
+            density: None,
+            boundaries: Vec::new(),
+        }
+    }
+
+    /// Ends chunks before each of `boundaries`, the first blocks of worker ranges.
+    pub(crate) fn with_boundaries(mut self, boundaries: impl IntoIterator<Item = u64>) -> Self {
+        self.boundaries = boundaries.into_iter().collect();
+        self.boundaries.sort_unstable();
+        self
+    }
+
+    /// The first block not handed out in a chunk yet.
+    pub(crate) fn next_block(&self) -> u64 {
+        self.next_block
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.next_block > self.end_block
+    }
+
+    /// Hands out the next chunk, or `None` once the whole range has been planned.
+    pub(crate) fn next_chunk(&mut self) -> Option<(u64, u64)> {
+        if self.is_done() {
+            return None;
+        }
+        let chunk_start = self.next_block;
+        let mut chunk_end = chunk_start
+            .saturating_add(self.chunk_size - 1)
+            .min(self.end_block);
+        let next_boundary = self
+            .boundaries
+            .partition_point(|&boundary| boundary <= chunk_start);
+        if let Some(&boundary) = self.boundaries.get(next_boundary) {
+            chunk_end = chunk_end.min(boundary - 1);
+        }
+        self.next_block = chunk_end + 1;
+        Some((chunk_start, chunk_end))
+    }
+
+    /// Updates the chunk size from a fetched chunk. Chunks served entirely from the cache report
+    /// no bytes and are ignored.
+    pub(crate) fn record(&mut self, sample: ChunkSample) {
+        let Some(sizing) = &self.sizing else {
+            return;
+        };
+        if sample.blocks == 0 || sample.bytes == 0 {
+            return;
+        }
+        let bytes_per_block = sample.bytes as f64 / sample.blocks as f64;
+        let items_per_block = sample.items as f64 / sample.blocks as f64;
+        let (bytes_per_block, items_per_block) = match self.density {
+            Some((bytes, items)) => (
+                bytes + SMOOTHING * (bytes_per_block - bytes),
+                items + SMOOTHING * (items_per_block - items),
+            ),
+            None => (bytes_per_block, items_per_block),
+        };
+        self.density = Some((bytes_per_block, items_per_block));
+        self.chunk_size = sizing.chunk_size(bytes_per_block, items_per_block, self.chunk_size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasource::{Datasource, DatasourceConfig};
+    use crate::mock_archive::MockArchive;
+    use serde_json::json;
+
+    fn sample(blocks: u64, bytes: u64, items: u64) -> ChunkSample {
+        ChunkSample {
+            blocks,
+            bytes,
+            items,
+        }
+    }
+
+    #[test]
+    fn test_fixed_chunks() {
+        let mut planner = ChunkPlanner::new(0, 25, 10, None);
+        planner.record(sample(10, 1 << 30, 0));
+        let chunks: Vec<_> = std::iter::from_fn(|| planner.next_chunk()).collect();
+        assert_eq!(chunks, vec![(0, 9), (10, 19), (20, 25)]);
+        assert!(ChunkPlanner::new(10, 5, 10, None).next_chunk().is_none());
+    }
+
+    #[test]
+    fn test_chunks_end_at_boundaries() {
+        let mut planner = ChunkPlanner::new(5, 40, 10, None).with_boundaries([20, 10, 32]);
+        let chunks: Vec<_> = std::iter::from_fn(|| planner.next_chunk()).collect();
+        assert_eq!(chunks, vec![(5, 9), (10, 19), (20, 29), (30, 31), (32, 40)]);
+    }
+
+    #[test]
+    fn test_chunks_adapt_to_density() {
+        let sizing = ChunkSizing::default()
+            .with_target_bytes(1000)
+            .with_chunk_size_limits(5, 10_000);
+        let mut planner = ChunkPlanner::new(0, 1_000_000, 100, Some(sizing));
+        assert_eq!(planner.next_chunk(), Some((0, 99)));
+
+        // 100 bytes per block: 10 blocks hit the target.
+        planner.record(sample(100, 10_000, 0));
+        assert_eq!(planner.next_chunk(), Some((100, 109)));
+
+        // The average only moves halfway towards a new sample: 50.5 bytes per block.
+        planner.record(sample(10, 10, 0));
+        assert_eq!(planner.chunk_size, 19);
+
+        // A sparse stretch merges blocks into ever larger chunks.
+        for _ in 0..10 {
+            planner.record(sample(1000, 10, 0));
+        }
+        assert_eq!(planner.chunk_size, 10_000);
+
+        // Ignored: served from the cache.
+        planner.record(sample(1000, 0, 500));
+        assert_eq!(planner.chunk_size, 10_000);
+
+        // A hot contract shrinks them down to the minimum.
+        planner.record(sample(10, 1 << 20, 0));
+        assert_eq!(planner.chunk_size, 5);
+    }
+
+    #[test]
+    fn test_growth_is_limited() {
+        let mut planner = ChunkPlanner::new(0, 1_000_000, 100, Some(ChunkSizing::default()));
+        planner.record(sample(1000, 10, 0));
+        assert_eq!(planner.chunk_size, 400);
+    }
+
+    #[test]
+    fn test_item_target() {
+        let sizing = ChunkSizing::default().with_target_items(100);
+        let mut planner = ChunkPlanner::new(0, 1_000_000, 1000, Some(sizing));
+        planner.record(sample(1000, 1000, 10_000));
+        assert_eq!(planner.chunk_size, 10);
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_chunks_cover_range() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/blocks.json");
+        let server = MockArchive::from_fixture(fixture)
+            .unwrap()
+            .with_worker_range_size(10)
+            .with_max_blocks_per_response(4)
+            .start()
+            .await
+            .unwrap();
+        let sizing = ChunkSizing::default()
+            .with_target_items(4)
+            .with_chunk_size_limits(1, 100);
+        let config = DatasourceConfig::new(server.url(), 2).with_chunk_sizing(sizing);
+        let query = json!({
+            "logs": [{"address": ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"]}],
+            "fields": {"log": {"address": true, "topics": true, "data": true}},
+        });
+
+        let frames = Datasource::new(config)
+            .get_parallelel_chunks(query, 14000000, 14000029, 2)
+            .await
+            .unwrap();
+        assert_eq!(frames.iter().map(|df| df.height()).sum::<usize>(), 27);
+        assert!(frames.len() < 15);
+    }
+}
@@ -0,0 +1,200 @@
+//! Progress reporting for range fetches.
+//!
+//! A `ProgressObserver` set on a `Datasource` is told about every batch a range fetch completes,
+//! which is enough to draw a progress bar with an ETA or forward progress to another UI.
+
+use crate::telemetry::dataset_items;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// How far a range fetch has got.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// The first block of the range.
+    pub start_block: u64,
+    /// The last block of the range, the target the fetch works towards.
+    pub end_block: u64,
+    /// How many blocks of the range have been scanned.
+    pub blocks_completed: u64,
+    /// Response bytes received from workers. Batches served from the cache add nothing.
+    pub bytes_downloaded: u64,
+    /// Items received per dataset, e.g. `"logs"` or `"transactions"`, plus `"blocks"`.
+    pub items: BTreeMap<String, u64>,
+    /// The worker that served the latest batch, or `None` if it came from the cache.
+    pub worker_url: Option<String>,
+}
+
+impl Progress {
+    /// The number of blocks in the range.
+    pub fn total_blocks(&self) -> u64 {
+        (self.end_block + 1).saturating_sub(self.start_block)
+    }
+
+    /// The completed share of the range, from 0.0 to 1.0.
+    pub fn fraction(&self) -> f64 {
+        match self.total_blocks() {
+            0 => 1.0,
+            total => self.blocks_completed as f64 / total as f64,
+        }
+    }
+}
+
+/// Receives progress updates from range fetches.
+///
+/// `on_progress` is called once per completed batch, from the task that fetched it, so it should
+/// return quickly. Parallel fetches report their combined progress.
+///
+/// # Examples
+///
+/// no_run
+/// struct PrintProgress;
+///
+/// impl ProgressObserver for PrintProgress {
+///     fn on_progress(&self, progress: &Progress) {
+///         println!("{:.1}%", progress.fraction() * 100.0);
+///     }
+/// }
+///
+/// let datasource = Datasource::new(config).with_progress_observer(Arc::new(PrintProgress));
+///
+pub trait ProgressObserver: Send + Sync {
+    fn on_progress(&self, progress: &Progress);
+}
+
+/// Accumulates the progress of one range fetch and reports it to the observer, if any.
+pub(crate) struct ProgressTracker {
+    observer: Option<Arc<dyn ProgressObserver>>,
+    progress: Mutex<Progress>,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(
+        observer: Option<Arc<dyn ProgressObserver>>,
+        start_block: u64,
+        end_block: u64,
+    ) -> Self {
+        Self {
+            observer,
+            progress: Mutex::new(Progress {
+                start_block,
+                end_block,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Lowers the target of the fetch to `end_block`, after the range was clamped to the dataset
+    /// height.
+    pub(crate) fn clamp_end(&self, end_block: u64) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.end_block = progress.end_block.min(end_block);
+    }
+
+    /// Records a batch that scanned `from_block..=last_block`. Blocks past `end_block`, the end of
+    /// the range or chunk being fetched, are not counted as completed.
+    pub(crate) fn record(
+        &self,
+        from_block: u64,
+        last_block: u64,
+        end_block: u64,
+        blocks: &[Value],
+        bytes: usize,
+        worker_url: Option<&str>,
+    ) {
+        let Some(observer) = &self.observer else {
+            return;
+        };
+
+        let mut progress = self.progress.lock().unwrap();
+        let scanned_to = last_block.min(end_block).min(progress.end_block);
+        progress.blocks_completed += (scanned_to + 1).saturating_sub(from_block);
+        progress.bytes_downloaded += bytes as u64;
+        *progress.items.entry("blocks".to_string()).or_default() += blocks.len() as u64;
+        for (dataset, items) in dataset_items(blocks) {
+            *progress.items.entry(dataset.to_string()).or_default() += items as u64;
+        }
+        progress.worker_url = worker_url.map(str::to_string);
+        observer.on_progress(&progress);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasource::{Datasource, DatasourceConfig};
+    use crate::mock_archive::MockArchive;
+    use serde_json::json;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Progress>>);
+
+    impl ProgressObserver for Recorder {
+        fn on_progress(&self, progress: &Progress) {
+            self.0.lock().unwrap().push(progress.clone());
+        }
+    }
+
+    #[test]
+    fn test_tracker_accumulates_batches() {
+        let recorder = Arc::new(Recorder::default());
+        let tracker = ProgressTracker::new(Some(recorder.clone()), 10, 29);
+
+        let blocks = vec![
+            json!({"header": {"number": 12}, "logs": [{}, {}]}),
+            json!({"header": {"number": 19}, "logs": [{}], "transactions": [{}]}),
+        ];
+        tracker.record(10, 19, 29, &blocks, 100, Some("http://w1"));
+        tracker.record(20, 35, 29, &[json!({"header": {"number": 35}})], 50, None);
+
+        let updates = recorder.0.lock().unwrap();
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].blocks_completed, 10);
+        assert_eq!(updates[0].fraction(), 0.5);
+        assert_eq!(updates[0].worker_url.as_deref(), Some("http://w1"));
+
+        let last = &updates[1];
+        assert_eq!(last.blocks_completed, 20);
+        assert_eq!(last.fraction(), 1.0);
+        assert_eq!(last.bytes_downloaded, 150);
+        assert_eq!(last.items["blocks"], 3);
+        assert_eq!(last.items["logs"], 3);
+        assert_eq!(last.items["transactions"], 1);
+        assert_eq!(last.worker_url, None);
+    }
+
+    #[tokio::test]
+    async fn test_parallel_chunks_report_combined_progress() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/blocks.json");
+        let server = MockArchive::from_fixture(fixture)
+            .unwrap()
+            .with_worker_range_size(10)
+            .with_max_blocks_per_response(4)
+            .start()
+            .await
+            .unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let datasource = Datasource::new(DatasourceConfig::new(server.url(), 3))
+            .with_progress_observer(recorder.clone());
+
+        let query = json!({
+            "logs": [{"address": ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"]}],
+            "fields": {"log": {"address": true, "topics": true, "data": true}},
+        });
+        datasource
+            .get_parallelel_chunks(query, 14000000, 14000029, 10)
+            .await
+            .unwrap();
+
+        let updates = recorder.0.lock().unwrap();
+        let last = updates.last().unwrap();
+        assert_eq!(last.blocks_completed, 30);
+        assert_eq!(last.fraction(), 1.0);
+        assert_eq!(last.items["logs"], 27);
+        assert!(last.bytes_downloaded > 0);
+        assert!(last.worker_url.is_some());
+        assert!(updates
+            .windows(2)
+            .all(|pair| pair[0].blocks_completed < pair[1].blocks_completed));
+    }
+}
@@ -556,12 +556,21 @@ mod tests {
 
               }
             ],
+            "traces": [
+              {
+                "type": ["call"]
+              }
+            ],
             "fields": {
 
               "log": {
                 "address": true,
                 "topics": true,
                 "data": true
+              },
+              "trace": {
+                "transactionIndex": true,
+                "traceAddress": true
               }
 
             },
@@ -0,0 +1,76 @@
+use governor::{
+    clock::DefaultClock,
+    middleware::NoOpMiddleware,
+    state::{direct::NotKeyed, InMemoryState},
+    Quota, RateLimiter,
+};
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+/// The unkeyed, in-memory governor rate limiter used by `Datasource`.
+pub type DirectRateLimiter = RateLimiter<NotKeyed, InMemoryState, DefaultClock, NoOpMiddleware>;
+
+/// A request rate for one kind of archive call, with an optional burst size.
+///
+/// Zero rates and bursts are treated as one.
+///
+/// # Examples
+///
+/// no_run
+/// let limit = RateLimit::per_second(5).with_burst(10);
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    quota: Quota,
+}
+
+impl RateLimit {
+    /// Allows `requests` requests per second.
+    pub fn per_second(requests: u32) -> Self {
+        Self {
+            quota: Quota::per_second(non_zero(requests)),
+        }
+    }
+
+    /// Allows `requests` requests per minute.
+    pub fn per_minute(requests: u32) -> Self {
+        Self {
+            quota: Quota::per_minute(non_zero(requests)),
+        }
+    }
+
+    /// Allows up to `burst` requests to be made at once before the rate applies.
+    pub fn with_burst(self, burst: u32) -> Self {
+        Self {
+            quota: self.quota.allow_burst(non_zero(burst)),
+        }
+    }
+
+    /// Builds a shareable rate limiter enforcing this limit.
+    pub fn build(self) -> Arc<DirectRateLimiter> {
+        Arc::new(RateLimiter::direct(self.quota))
+    }
+}
+
+fn non_zero(value: u32) -> NonZeroU32 {
+    NonZeroU32::new(value).unwrap_or(NonZeroU32::MIN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_allows_immediate_requests() {
+        let limiter = RateLimit::per_minute(1).with_burst(3).build();
+        for _ in 0..3 {
+            assert!(limiter.check().is_ok());
+        }
+        assert!(limiter.check().is_err());
+    }
+
+    #[test]
+    fn test_zero_rate_is_clamped() {
+        assert_eq!(RateLimit::per_second(0), RateLimit::per_second(1));
+    }
+}
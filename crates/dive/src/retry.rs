@@ -0,0 +1,115 @@
+use crate::error::DatasourceError;
+use crate::telemetry;
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+/// Retry policy for requests to the archive router and workers.
+///
+/// Delays grow exponentially from `base_delay` and are capped at `max_delay`. `jitter` is the
+/// fraction of each delay that is randomised, so `0.0` disables jitter and `1.0` gives full jitter.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes a single attempt and never retries.
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Returns how long to wait after the given failed attempt (starting at 1).
+    ///
+    /// # Examples
+    ///
+    /// no_run
+    /// let delay = RetryPolicy::default().delay_for(3);
+    ///
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+        let factor = 1.0 - jitter * rand::thread_rng().gen::<f64>();
+        delay.mul_f64(factor)
+    }
+
+    /// Runs `operation`, retrying retryable failures with the delays of this policy.
+    pub(crate) async fn retry<T, F, Fut>(&self, mut operation: F) -> Result<T, DatasourceError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, DatasourceError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.max_attempts && e.is_retryable() => {
+                    let delay = self.delay_for(attempt);
+                    warn!(attempt, ?delay, error = %e, "retrying failed request");
+                    telemetry::record_retry();
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: 0.0,
+        };
+        assert_eq!(policy.delay_for(1), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(4), Duration::from_millis(800));
+        assert_eq!(policy.delay_for(5), Duration::from_secs(1));
+        assert_eq!(policy.delay_for(64), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_delay_with_jitter_stays_in_bounds() {
+        let policy = RetryPolicy {
+            jitter: 1.0,
+            ..Default::default()
+        };
+        for attempt in 1..10 {
+            let delay = policy.delay_for(attempt);
+            assert!(delay <= policy.max_delay);
+        }
+    }
+}
@@ -0,0 +1,863 @@
+//! A `BlockSource` backed by an Ethereum JSON-RPC node, for blocks the archive has not indexed
+//! yet or networks it does not serve.
+//!
+//! `RpcSource` takes the same query JSON as the archive and translates it: log requests become
+//! `eth_getLogs` filters, and transaction and block requests fetch every block of the range with
+//! `eth_getBlockByNumber`, plus its receipts with `eth_getBlockReceipts` when receipt fields are
+//! selected. The results are converted to the archive shape, so `to_df::to_df` reads them
+//! unchanged.
+//!
+//! Trace requests are not supported, and relations such as `"transaction": true` on a log
+//! request are not followed.
+
+use crate::error::{BlockRange, DatasourceError, RPC_LIMIT_EXCEEDED};
+use crate::http::HttpConfig;
+use crate::rate_limit::{DirectRateLimiter, RateLimit};
+use crate::retry::RetryPolicy;
+use crate::source::BlockSource;
+use crate::transport::ReqwestTransport;
+use futures::future::BoxFuture;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use reqwest::Client;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use to_df::fields::hex_str_to_u64;
+
+/// Header quantities the archive returns as numbers. The rest, such as `gasUsed`, stay hex
+/// strings, as in archive responses.
+const HEADER_QUANTITIES: &[&str] = &[
+    "number",
+    "timestamp",
+    "baseFeePerGas",
+    "totalDifficulty",
+    "size",
+];
+
+/// Transaction and receipt quantities the archive returns as numbers. `value` and `gasUsed` stay
+/// hex strings.
+const TRANSACTION_QUANTITIES: &[&str] = &[
+    "transactionIndex",
+    "gas",
+    "gasPrice",
+    "maxFeePerGas",
+    "maxPriorityFeePerGas",
+    "nonce",
+    "v",
+    "yParity",
+    "chainId",
+    "type",
+    "cumulativeGasUsed",
+    "effectiveGasPrice",
+    "status",
+];
+
+const LOG_QUANTITIES: &[&str] = &["logIndex", "transactionIndex"];
+
+/// Transaction fields that only receipts hold.
+const RECEIPT_FIELDS: &[&str] = &[
+    "gasUsed",
+    "cumulativeGasUsed",
+    "effectiveGasPrice",
+    "contractAddress",
+    "status",
+];
+
+/// Blocks served by an Ethereum JSON-RPC node, such as a local anvil node or a provider endpoint.
+///
+/// # Examples
+///
+/// no_run
+/// let source = RpcSource::new("http://127.0.0.1:8545")
+///     .with_max_log_range(2_000)
+///     .with_rate_limit(RateLimit::per_second(10));
+/// let df = source::get_as_df(&source, query, 100, 200).await?;
+///
+#[derive(Debug)]
+pub struct RpcSource {
+    url: String,
+    client: Client,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<Arc<DirectRateLimiter>>,
+    max_concurrent_requests: usize,
+    max_log_range: u64,
+    next_id: AtomicU64,
+}
+
+impl RpcSource {
+    /// Creates a source for the node at `url`. It makes up to 10 requests at a time and asks
+    /// `eth_getLogs` for at most 10,000 blocks per call.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            client: Client::new(),
+            retry_policy: RetryPolicy::default(),
+            rate_limiter: None,
+            max_concurrent_requests: 10,
+            max_log_range: 10_000,
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Sends requests with `client` instead of a default one.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sends requests with a client built from `http`, for timeouts, a proxy or auth headers.
+    /// Fails with `DatasourceError::ClientConfig` if the client cannot be built.
+    pub fn with_http(self, http: &HttpConfig) -> Result<Self, DatasourceError> {
+        let client = http
+            .build_client()
+            .map_err(|source| DatasourceError::ClientConfig { source })?;
+        Ok(self.with_client(client))
+    }
+
+    /// Limits the rate of requests to the node, retries included.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limiter = Some(limit.build());
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        self.max_concurrent_requests = max_concurrent_requests.max(1);
+        self
+    }
+
+    /// Splits `eth_getLogs` calls into ranges of at most `blocks` blocks, for nodes that limit
+    /// the range of a log query.
+    pub fn with_max_log_range(mut self, blocks: u64) -> Self {
+        self.max_log_range = blocks.max(1);
+        self
+    }
+
+    /// Sends a JSON-RPC request and returns its result, retrying retryable failures.
+    async fn call(
+        &self,
+        method: &str,
+        params: Value,
+        range: Option<BlockRange>,
+    ) -> Result<Value, DatasourceError> {
+        self.retry_policy
+            .retry(|| async {
+                if let Some(rate_limiter) = &self.rate_limiter {
+                    rate_limiter.until_ready().await;
+                }
+                let request = json!({
+                    "jsonrpc": "2.0",
+                    "id": self.next_id.fetch_add(1, Ordering::Relaxed),
+                    "method": method,
+                    "params": params,
+                });
+                let body = ReqwestTransport::send(
+                    self.client.post(&self.url).json(&request),
+                    &self.url,
+                    range,
+                )
+                .await?;
+                let mut response: Value =
+                    serde_json::from_str(&body).map_err(|source| DatasourceError::Decode {
+                        url: self.url.clone(),
+                        range,
+                        source,
+                    })?;
+                if let Some(error) = response.get("error") {
+                    return Err(match error["code"].as_i64() {
+                        Some(code) => DatasourceError::Rpc {
+                            code,
+                            message: error["message"].as_str().unwrap_or_default().to_string(),
+                            url: self.url.clone(),
+                            range,
+                        },
+                        None => self.unexpected(error.clone(), range),
+                    });
+                }
+                let result = response.get_mut("result").map(Value::take);
+                result.ok_or_else(|| self.unexpected(response, range))
+            })
+            .await
+    }
+
+    fn unexpected(&self, payload: Value, range: Option<BlockRange>) -> DatasourceError {
+        DatasourceError::ArchiveError {
+            payload,
+            url: self.url.clone(),
+            range,
+        }
+    }
+
+    /// Runs every log request over `start_block..=end_block` with `eth_getLogs`.
+    async fn get_logs(
+        &self,
+        requests: &[Value],
+        start_block: u64,
+        end_block: u64,
+    ) -> Result<Vec<Value>, DatasourceError> {
+        let mut ranges = Vec::new();
+        for index in 0..requests.len() {
+            let mut from_block = start_block;
+            while from_block <= end_block {
+                let to_block = from_block
+                    .saturating_add(self.max_log_range - 1)
+                    .min(end_block);
+                ranges.push((index, from_block, to_block));
+                from_block = to_block + 1;
+            }
+        }
+        let batches: Vec<Vec<Value>> = stream::iter(ranges)
+            .map(|(index, from_block, to_block)| {
+                self.get_logs_in_range(&requests[index], from_block, to_block)
+            })
+            .buffered(self.max_concurrent_requests)
+            .try_collect()
+            .await?;
+        Ok(batches.into_iter().flatten().collect())
+    }
+
+    /// Runs one log request over `from_block..=to_block`. If the node refuses because the call
+    /// matches too many logs, each half of the range is fetched instead.
+    fn get_logs_in_range<'a>(
+        &'a self,
+        request: &'a Value,
+        from_block: u64,
+        to_block: u64,
+    ) -> BoxFuture<'a, Result<Vec<Value>, DatasourceError>> {
+        Box::pin(async move {
+            let range = Some(BlockRange::new(from_block, to_block));
+            let filter = log_filter(request, from_block, to_block);
+            match self.call("eth_getLogs", json!([filter]), range).await {
+                Ok(Value::Array(logs)) => Ok(logs),
+                Ok(other) => Err(self.unexpected(other, range)),
+                Err(e) if is_too_many_logs(&e) && from_block < to_block => {
+                    let middle = from_block + (to_block - from_block) / 2;
+                    let mut logs = self.get_logs_in_range(request, from_block, middle).await?;
+                    logs.extend(
+                        self.get_logs_in_range(request, middle + 1, to_block)
+                            .await?,
+                    );
+                    Ok(logs)
+                }
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    /// Fetches block `number` with the transactions `query` selects, in the archive shape.
+    async fn fetch_block(&self, query: &Value, number: u64) -> Result<Block, DatasourceError> {
+        let range = Some(BlockRange::new(number, number));
+        let fields = &query["fields"];
+        let requests = query.get("transactions").and_then(Value::as_array);
+        let block = self
+            .call(
+                "eth_getBlockByNumber",
+                json!([hex(number), requests.is_some()]),
+                range,
+            )
+            .await?;
+        if !block.is_object() {
+            return Err(self.unexpected(block, range));
+        }
+
+        let mut transactions: Vec<Map<String, Value>> = block["transactions"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|transaction| {
+                let mut transaction = to_archive(transaction, TRANSACTION_QUANTITIES);
+                let sighash = transaction
+                    .get("input")
+                    .and_then(Value::as_str)
+                    .and_then(|input| input.get(..10))
+                    .map(str::to_string);
+                if let Some(sighash) = sighash {
+                    transaction.insert("sighash".to_string(), sighash.into());
+                }
+                transaction
+            })
+            .filter(|transaction| {
+                requests
+                    .into_iter()
+                    .flatten()
+                    .any(|request| matches_transaction(transaction, request))
+            })
+            .collect();
+
+        if !transactions.is_empty() && selects_any(&fields["transaction"], RECEIPT_FIELDS) {
+            let receipts = match self
+                .call("eth_getBlockReceipts", json!([hex(number)]), range)
+                .await?
+            {
+                Value::Array(receipts) => receipts,
+                other => return Err(self.unexpected(other, range)),
+            };
+            let receipts: HashMap<&str, &Value> = receipts
+                .iter()
+                .filter_map(|receipt| Some((receipt["transactionHash"].as_str()?, receipt)))
+                .collect();
+            for transaction in &mut transactions {
+                let receipt = transaction
+                    .get("hash")
+                    .and_then(Value::as_str)
+                    .and_then(|hash| receipts.get(hash));
+                let Some(receipt) = receipt else {
+                    continue;
+                };
+                let receipt = to_archive(receipt, TRANSACTION_QUANTITIES);
+                for field in RECEIPT_FIELDS {
+                    if let Some(value) = receipt.get(*field) {
+                        transaction.insert(field.to_string(), value.clone());
+                    }
+                }
+            }
+        }
+
+        let header = to_archive(&block, HEADER_QUANTITIES);
+        Ok(Block {
+            header: select_fields(&header, &fields["block"], &["number", "hash"]),
+            logs: Vec::new(),
+            transactions: transactions
+                .iter()
+                .map(|transaction| {
+                    let selected =
+                        select_fields(transaction, &fields["transaction"], &["transactionIndex"]);
+                    Value::Object(selected)
+                })
+                .collect(),
+        })
+    }
+}
+
+impl BlockSource for RpcSource {
+    fn height(&self) -> BoxFuture<'_, Result<u64, DatasourceError>> {
+        Box::pin(async move {
+            let result = self.call("eth_blockNumber", json!([]), None).await?;
+            quantity(&result).ok_or_else(|| self.unexpected(result, None))
+        })
+    }
+
+    fn fetch_range<'a>(
+        &'a self,
+        query: &'a Value,
+        start_block: u64,
+        end_block: u64,
+    ) -> BoxFuture<'a, Result<Vec<Value>, DatasourceError>> {
+        Box::pin(async move {
+            if query.get("traces").is_some() {
+                return Err(DatasourceError::UnsupportedQuery {
+                    reason: "traces are not available over JSON-RPC".to_string(),
+                });
+            }
+            if start_block > end_block {
+                return Ok(Vec::new());
+            }
+            let height = self.height().await?;
+            if start_block > height {
+                return Err(DatasourceError::RangeBeyondHeight {
+                    range: BlockRange::new(start_block, end_block),
+                    height,
+                });
+            }
+            let end_block = end_block.min(height);
+            let fields = &query["fields"];
+            let mut blocks: BTreeMap<u64, Block> = BTreeMap::new();
+
+            if let Some(requests) = query.get("logs").and_then(Value::as_array) {
+                // Several requests can match the same log; key them to keep one of each.
+                let mut logs = BTreeMap::new();
+                for log in self.get_logs(requests, start_block, end_block).await? {
+                    if log["removed"].as_bool() == Some(true) {
+                        continue;
+                    }
+                    match (quantity(&log["blockNumber"]), quantity(&log["logIndex"])) {
+                        (Some(number), Some(index)) => logs.insert((number, index), log),
+                        _ => return Err(self.unexpected(log, None)),
+                    };
+                }
+                for ((number, _), log) in logs {
+                    let block = blocks.entry(number).or_insert_with(|| Block {
+                        header: header_stub(number, &log),
+                        ..Default::default()
+                    });
+                    let log = to_archive(&log, LOG_QUANTITIES);
+                    let selected =
+                        select_fields(&log, &fields["log"], &["logIndex", "transactionIndex"]);
+                    block.logs.push(Value::Object(selected));
+                }
+            }
+
+            let include_all = query["includeAllBlocks"].as_bool().unwrap_or(false)
+                || query.get("blocks").is_some();
+            let numbers: Vec<u64> = if include_all || query.get("transactions").is_some() {
+                (start_block..=end_block).collect()
+            } else if selects_header_fields(&fields["block"]) {
+                blocks.keys().copied().collect()
+            } else {
+                Vec::new()
+            };
+            let fetched: Vec<(u64, Block)> = stream::iter(numbers)
+                .map(|number| async move {
+                    let block = self.fetch_block(query, number).await?;
+                    Ok::<_, DatasourceError>((number, block))
+                })
+                .buffered(self.max_concurrent_requests)
+                .try_collect()
+                .await?;
+            for (number, fetched) in fetched {
+                let block = blocks.entry(number).or_default();
+                block.header = fetched.header;
+                block.transactions = fetched.transactions;
+            }
+
+            Ok(blocks
+                .into_values()
+                .filter(|block| {
+                    include_all || !block.logs.is_empty() || !block.transactions.is_empty()
+                })
+                .map(|block| block.into_value(query))
+                .collect())
+        })
+    }
+}
+
+/// A block being assembled in the archive shape.
+#[derive(Debug, Default)]
+struct Block {
+    header: Map<String, Value>,
+    logs: Vec<Value>,
+    transactions: Vec<Value>,
+}
+
+impl Block {
+    /// Returns the block with the datasets `query` requests, like an archive response.
+    fn into_value(self, query: &Value) -> Value {
+        let mut block = Map::new();
+        block.insert("header".to_string(), Value::Object(self.header));
+        if query.get("logs").is_some() {
+            block.insert("logs".to_string(), Value::Array(self.logs));
+        }
+        if query.get("transactions").is_some() {
+            block.insert("transactions".to_string(), Value::Array(self.transactions));
+        }
+        Value::Object(block)
+    }
+}
+
+/// Returns true if the node refused an `eth_getLogs` call because it matches too many logs, such
+/// as Infura's "query returned more than 10000 results". Throttling uses the same code but is
+/// retryable.
+fn is_too_many_logs(error: &DatasourceError) -> bool {
+    matches!(error, DatasourceError::Rpc { code, .. } if *code == RPC_LIMIT_EXCEEDED)
+        && !error.is_retryable()
+}
+
+/// Translates an archive log request to an `eth_getLogs` filter over `from_block..=to_block`.
+fn log_filter(request: &Value, from_block: u64, to_block: u64) -> Value {
+    let mut filter = json!({"fromBlock": hex(from_block), "toBlock": hex(to_block)});
+    if let Some(address) = request.get("address") {
+        filter["address"] = address.clone();
+    }
+    let mut topics: Vec<Value> = (0..4)
+        .map(|i| {
+            request
+                .get(format!("topic{}", i))
+                .cloned()
+                .unwrap_or(Value::Null)
+        })
+        .collect();
+    while topics.last() == Some(&Value::Null) {
+        topics.pop();
+    }
+    if !topics.is_empty() {
+        filter["topics"] = Value::Array(topics);
+    }
+    filter
+}
+
+/// The header of a block known only from its logs.
+fn header_stub(number: u64, log: &Value) -> Map<String, Value> {
+    let mut header = Map::new();
+    header.insert("number".to_string(), number.into());
+    if let Some(hash) = log.get("blockHash") {
+        header.insert("hash".to_string(), hash.clone());
+    }
+    header
+}
+
+/// Copies an RPC object, converting the listed hex quantities to numbers. Quantities too large
+/// for a `u64` stay hex strings.
+fn to_archive(item: &Value, quantities: &[&str]) -> Map<String, Value> {
+    let mut item = item.as_object().cloned().unwrap_or_default();
+    for field in quantities {
+        if let Some(value) = item.get_mut(*field) {
+            if let Some(number) = quantity(value) {
+                *value = number.into();
+            }
+        }
+    }
+    item
+}
+
+/// Keeps the fields `selection` selects, plus the `always` ones the archive returns regardless.
+fn select_fields(
+    item: &Map<String, Value>,
+    selection: &Value,
+    always: &[&str],
+) -> Map<String, Value> {
+    item.iter()
+        .filter(|(name, _)| {
+            always.contains(&name.as_str()) || selection[name.as_str()].as_bool() == Some(true)
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+fn selects_any(selection: &Value, fields: &[&str]) -> bool {
+    fields
+        .iter()
+        .any(|field| selection[*field].as_bool() == Some(true))
+}
+
+/// Returns true if `selection` asks for header fields beyond those logs carry.
+fn selects_header_fields(selection: &Value) -> bool {
+    selection.as_object().is_some_and(|selection| {
+        selection.iter().any(|(name, wanted)| {
+            wanted.as_bool() == Some(true) && name != "number" && name != "hash"
+        })
+    })
+}
+
+/// Returns true if a transaction passes every filter of an archive transaction request.
+fn matches_transaction(transaction: &Map<String, Value>, request: &Value) -> bool {
+    ["from", "to", "sighash"].iter().all(|name| {
+        let Some(allowed) = request.get(*name).and_then(Value::as_array) else {
+            return true;
+        };
+        transaction
+            .get(*name)
+            .and_then(Value::as_str)
+            .is_some_and(|actual| {
+                allowed
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .any(|allowed| allowed.eq_ignore_ascii_case(actual))
+            })
+    })
+}
+
+fn quantity(value: &Value) -> Option<u64> {
+    value.as_str().and_then(|hex| hex_str_to_u64(hex).ok())
+}
+
+fn hex(number: u64) -> String {
+    format!("{:#x}", number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasource::{Datasource, DatasourceConfig};
+    use crate::mock_archive::{read_request, write_response, MockArchive, Response};
+    use crate::source;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/blocks.json");
+    const USDC: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+
+    /// Encodes every number in an archive object as a hex quantity, as a node returns it.
+    fn to_rpc(item: &Value) -> Value {
+        let mut item = item.as_object().cloned().unwrap_or_default();
+        for value in item.values_mut() {
+            if let Some(number) = value.as_u64() {
+                *value = hex(number).into();
+            }
+        }
+        Value::Object(item)
+    }
+
+    /// Answers a JSON-RPC request from the archive-shaped fixture blocks.
+    fn answer(blocks: &[Value], method: &str, params: &Value) -> Value {
+        let number = |block: &Value| block["header"]["number"].as_u64().unwrap();
+        match method {
+            "eth_blockNumber" => hex(blocks.iter().map(number).max().unwrap()).into(),
+            "eth_getLogs" => {
+                let filter = &params[0];
+                let range =
+                    quantity(&filter["fromBlock"]).unwrap()..=quantity(&filter["toBlock"]).unwrap();
+                let allowed = |allowed: &Value, actual: &Value| {
+                    allowed.is_null() || allowed.as_array().unwrap().contains(actual)
+                };
+                let mut logs = Vec::new();
+                for block in blocks.iter().filter(|block| range.contains(&number(block))) {
+                    for log in block["logs"].as_array().into_iter().flatten() {
+                        let topics = filter["topics"].as_array().cloned().unwrap_or_default();
+                        if allowed(&filter["address"], &log["address"])
+                            && topics
+                                .iter()
+                                .enumerate()
+                                .all(|(i, topic)| allowed(topic, &log["topics"][i]))
+                        {
+                            let mut log = to_rpc(log);
+                            log["blockNumber"] = hex(number(block)).into();
+                            log["blockHash"] = block["header"]["hash"].clone();
+                            logs.push(log);
+                        }
+                    }
+                }
+                Value::Array(logs)
+            }
+            "eth_getBlockByNumber" | "eth_getBlockReceipts" => {
+                let requested = quantity(&params[0]).unwrap();
+                let Some(block) = blocks.iter().find(|block| number(block) == requested) else {
+                    return Value::Null;
+                };
+                let transactions = block["transactions"]
+                    .as_array()
+                    .cloned()
+                    .unwrap_or_default();
+                if method == "eth_getBlockReceipts" {
+                    let receipts = transactions.iter().map(|transaction| {
+                        let status = transaction["status"].as_u64().unwrap();
+                        json!({
+                            "transactionHash": transaction["hash"],
+                            "gasUsed": transaction["gasUsed"],
+                            "status": hex(status),
+                        })
+                    });
+                    return receipts.collect();
+                }
+                let mut rpc_block = to_rpc(&block["header"]);
+                rpc_block["transactions"] = if params[1] == json!(true) {
+                    let transactions = transactions.iter().map(|transaction| {
+                        let mut transaction = to_rpc(transaction);
+                        let object = transaction.as_object_mut().unwrap();
+                        for receipt_field in ["gasUsed", "status", "sighash"] {
+                            object.remove(receipt_field);
+                        }
+                        transaction
+                    });
+                    transactions.collect()
+                } else {
+                    transactions.iter().map(|tx| tx["hash"].clone()).collect()
+                };
+                rpc_block
+            }
+            _ => panic!("unexpected method {}", method),
+        }
+    }
+
+    /// Serves the fixture over JSON-RPC and returns the node URL. The first requests are answered
+    /// with `errors`, one each.
+    async fn start_node(blocks: Vec<Value>, errors: Vec<Value>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let blocks = Arc::new(blocks);
+        let errors = Arc::new(Mutex::new(VecDeque::from(errors)));
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (blocks, errors) = (blocks.clone(), errors.clone());
+                tokio::spawn(async move {
+                    let request = read_request(&mut stream).await.unwrap();
+                    let request: Value = serde_json::from_slice(&request.body).unwrap();
+                    let method = request["method"].as_str().unwrap();
+                    let error = errors.lock().unwrap().pop_front();
+                    let response = match error {
+                        Some(error) => {
+                            json!({"jsonrpc": "2.0", "id": request["id"], "error": error})
+                        }
+                        None => json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "result": answer(&blocks, method, &request["params"]),
+                        }),
+                    };
+                    write_response(&mut stream, Response::Ok(response.to_string()))
+                        .await
+                        .unwrap();
+                });
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_log_filters() {
+        let request = json!({
+            "address": [USDC],
+            "topic0": ["0xddf252ad"],
+            "topic2": ["0x01", "0x02"],
+        });
+        assert_eq!(
+            log_filter(&request, 16, 31),
+            json!({
+                "fromBlock": "0x10",
+                "toBlock": "0x1f",
+                "address": [USDC],
+                "topics": [["0xddf252ad"], null, ["0x01", "0x02"]],
+            })
+        );
+        assert_eq!(
+            log_filter(&json!({}), 0, 0),
+            json!({"fromBlock": "0x0", "toBlock": "0x0"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rpc_matches_archive() {
+        let blocks: Vec<Value> =
+            serde_json::from_str(&std::fs::read_to_string(FIXTURE).unwrap()).unwrap();
+        let rpc =
+            RpcSource::new(start_node(blocks.clone(), Vec::new()).await).with_max_log_range(7);
+        let server = MockArchive::new(blocks)
+            .with_worker_range_size(10)
+            .start()
+            .await
+            .unwrap();
+        let datasource = Datasource::new(DatasourceConfig::new(server.url(), 2));
+
+        let queries = [
+            json!({
+                "logs": [{"address": [USDC]}],
+                "fields": {
+                    "log": {"logIndex": true, "transactionHash": true, "topics": true, "data": true},
+                },
+            }),
+            json!({
+                "transactions": [{"to": [USDC], "sighash": ["0xa9059cbb"]}],
+                "fields": {
+                    "transaction": {"hash": true, "from": true, "nonce": true, "gasUsed": true, "status": true},
+                },
+            }),
+        ];
+        for query in queries {
+            let expected = source::get_as_df(&datasource, query.clone(), 14000000, 14000029)
+                .await
+                .unwrap();
+            let df = source::get_as_df(&rpc, query, 14000000, 14000029)
+                .await
+                .unwrap();
+            assert!(df.height() > 0);
+            assert_eq!(df, expected);
+        }
+
+        let query = json!({
+            "logs": [{"address": [USDC]}],
+            "fields": {"block": {"timestamp": true}, "log": {"address": true}},
+        });
+        let blocks = rpc.fetch_range(&query, 14000003, 14000004).await.unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0]["header"]["timestamp"], json!(1641070852));
+        assert!(matches!(
+            rpc.fetch_range(&query, 14000030, 14000040).await,
+            Err(DatasourceError::RangeBeyondHeight { .. })
+        ));
+        assert!(matches!(
+            rpc.fetch_range(&json!({"traces": [{}]}), 0, 1).await,
+            Err(DatasourceError::UnsupportedQuery { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_are_retried() {
+        let blocks: Vec<Value> =
+            serde_json::from_str(&std::fs::read_to_string(FIXTURE).unwrap()).unwrap();
+        let policy = RetryPolicy {
+            base_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let errors = vec![
+            json!({"code": -32005, "message": "daily request count exceeded, request rate limited"}),
+            json!({"code": -32007, "message": "request limit exceeded"}),
+            json!({"code": -32603, "message": "internal error"}),
+        ];
+        let rpc = RpcSource::new(start_node(blocks.clone(), errors).await)
+            .with_retry_policy(policy.clone());
+        assert_eq!(rpc.height().await.unwrap(), 14000029);
+
+        let errors = vec![json!({"code": -32602, "message": "invalid params"})];
+        let rpc = RpcSource::new(start_node(blocks, errors).await).with_retry_policy(policy);
+        let error = rpc.height().await.unwrap_err();
+        assert!(matches!(error, DatasourceError::Rpc { code: -32602, .. }));
+        assert!(!error.is_retryable());
+        assert_eq!(rpc.height().await.unwrap(), 14000029);
+    }
+
+    #[tokio::test]
+    async fn test_too_many_logs_splits_range() {
+        let blocks: Vec<Value> =
+            serde_json::from_str(&std::fs::read_to_string(FIXTURE).unwrap()).unwrap();
+        let requests = [json!({"address": [USDC]})];
+        let expected = RpcSource::new(start_node(blocks.clone(), Vec::new()).await)
+            .get_logs(&requests, 14000000, 14000029)
+            .await
+            .unwrap();
+        assert!(!expected.is_empty());
+
+        // The whole range and then its first half match too many logs.
+        let too_many = json!({
+            "code": -32005,
+            "message": "query returned more than 10000 results",
+        });
+        let rpc = RpcSource::new(start_node(blocks, vec![too_many.clone(), too_many]).await)
+            .with_retry_policy(RetryPolicy::no_retry())
+            .with_max_concurrent_requests(1);
+        let logs = rpc.get_logs(&requests, 14000000, 14000029).await.unwrap();
+        assert_eq!(logs, expected);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_applies() {
+        let blocks: Vec<Value> =
+            serde_json::from_str(&std::fs::read_to_string(FIXTURE).unwrap()).unwrap();
+        let rpc = RpcSource::new(start_node(blocks, Vec::new()).await)
+            .with_rate_limit(RateLimit::per_minute(1));
+
+        assert_eq!(rpc.height().await.unwrap(), 14000029);
+        let throttled = tokio::time::timeout(Duration::from_millis(100), rpc.height()).await;
+        assert!(throttled.is_err());
+    }
+
+    /// Needs a local node: start `anvil`, then run `cargo test -p dive rpc -- --ignored`. Set
+    /// `DIVE_RPC_URL` to use another node.
+    #[tokio::test]
+    #[ignore]
+    async fn test_anvil() {
+        let url =
+            std::env::var("DIVE_RPC_URL").unwrap_or_else(|_| "http://127.0.0.1:8545".to_string());
+        let source = RpcSource::new(url);
+        let height = source.height().await.unwrap();
+        let query = json!({
+            "includeAllBlocks": true,
+            "transactions": [{}],
+            "fields": {
+                "block": {"timestamp": true, "gasUsed": true},
+                "transaction": {"hash": true, "from": true, "gasUsed": true, "status": true},
+            },
+        });
+        let blocks = source.fetch_range(&query, 0, height).await.unwrap();
+        assert_eq!(blocks.len() as u64, height + 1);
+
+        let headers = to_df::to_df(
+            to_df::fields::Dataset::Blocks,
+            blocks.clone(),
+            vec!["number", "hash", "timestamp", "gasUsed"],
+        )
+        .unwrap();
+        assert_eq!(headers.height(), blocks.len());
+        let transactions = source::get_as_df(&source, query, 0, height).await.unwrap();
+        let count: usize = blocks
+            .iter()
+            .map(|block| block["transactions"].as_array().unwrap().len())
+            .sum();
+        assert_eq!(transactions.height(), count);
+    }
+}
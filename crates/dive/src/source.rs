@@ -0,0 +1,295 @@
+//! Backends that blocks can be fetched from.
+//!
+//! `BlockSource` is the range fetch every backend provides, returning blocks in the archive JSON
+//! shape. `Datasource` implements it for the Subsquid archive, `FileSource` for blocks stored
+//! in local JSON files and `rpc::RpcSource` for JSON-RPC nodes. The functions in this module
+//! build DataFrames and streams on top of any source.
+
+use crate::datasource::{blocks_to_df, owned_fields, query_dataset, Datasource};
+use crate::error::{BlockRange, DatasourceError};
+use anyhow::{Context, Error};
+use futures::future::{self, BoxFuture};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use polars::prelude::DataFrame;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// A backend that serves blocks by range.
+///
+/// # Examples
+///
+/// no_run
+/// let sources: Vec<Box<dyn BlockSource>> = vec![
+///     Box::new(Datasource::new(config)),
+///     Box::new(FileSource::open("blocks.json")?),
+/// ];
+/// for source in &sources {
+///     let df = source::get_as_df(source.as_ref(), query.clone(), 100, 200).await?;
+/// }
+///
+pub trait BlockSource: Send + Sync {
+    /// The highest block the source can serve.
+    fn height(&self) -> BoxFuture<'_, Result<u64, DatasourceError>>;
+
+    /// Fetches the blocks of `start_block..=end_block` selected by `query`, in block order and
+    /// in the archive JSON shape. Blocks without matching items may be left out.
+    fn fetch_range<'a>(
+        &'a self,
+        query: &'a Value,
+        start_block: u64,
+        end_block: u64,
+    ) -> BoxFuture<'a, Result<Vec<Value>, DatasourceError>>;
+}
+
+impl BlockSource for Datasource {
+    fn height(&self) -> BoxFuture<'_, Result<u64, DatasourceError>> {
+        Box::pin(self.get_dataset_height())
+    }
+
+    fn fetch_range<'a>(
+        &'a self,
+        query: &'a Value,
+        start_block: u64,
+        end_block: u64,
+    ) -> BoxFuture<'a, Result<Vec<Value>, DatasourceError>> {
+        Box::pin(self.get_data_in_range(query.clone(), start_block, end_block))
+    }
+}
+
+/// Fetches `start_block..=end_block` from `source` and converts it to a Polars DataFrame.
+///
+/// # Examples
+///
+/// no_run
+/// let df = source::get_as_df(&FileSource::open("blocks.json")?, query, 100, 200).await?;
+///
+pub async fn get_as_df<S: BlockSource + ?Sized>(
+    source: &S,
+    query: Value,
+    start_block: u64,
+    end_block: u64,
+) -> Result<DataFrame, DatasourceError> {
+    let dataset = query_dataset(&query)?;
+    let blocks = source.fetch_range(&query, start_block, end_block).await?;
+    blocks_to_df(dataset, owned_fields(&query), blocks).await
+}
+
+/// Streams `start_block..=end_block` from `source` in ranges of `batch_size` blocks. The stream
+/// ends after the first error.
+///
+/// # Examples
+///
+/// no_run
+/// let mut batches = std::pin::pin!(source::stream_range(&source, query, 100, 200, 50));
+/// while let Some(blocks) = batches.next().await {
+///     let blocks = blocks?;
+/// }
+///
+pub fn stream_range<S: BlockSource + ?Sized>(
+    source: &S,
+    query: Value,
+    start_block: u64,
+    end_block: u64,
+    batch_size: u64,
+) -> impl Stream<Item = Result<Vec<Value>, DatasourceError>> + '_ {
+    let batch_size = batch_size.max(1);
+    stream::try_unfold(
+        (query, start_block),
+        move |(query, from_block)| async move {
+            if from_block > end_block {
+                return Ok(None);
+            }
+            let to_block = from_block.saturating_add(batch_size - 1).min(end_block);
+            let blocks = source.fetch_range(&query, from_block, to_block).await?;
+            Ok(Some((blocks, (query, to_block + 1))))
+        },
+    )
+}
+
+/// Streams `start_block..=end_block` from `source` like `stream_range`, converting each batch to
+/// a Polars DataFrame.
+pub fn stream_as_df<S: BlockSource + ?Sized>(
+    source: &S,
+    query: Value,
+    start_block: u64,
+    end_block: u64,
+    batch_size: u64,
+) -> impl Stream<Item = Result<DataFrame, DatasourceError>> + '_ {
+    let dataset = match query_dataset(&query) {
+        Ok(dataset) => dataset,
+        Err(e) => return stream::once(future::ready(Err(e))).right_stream(),
+    };
+    let fields = owned_fields(&query);
+    stream_range(source, query, start_block, end_block, batch_size)
+        .and_then(move |blocks| blocks_to_df(dataset, fields.clone(), blocks))
+        .left_stream()
+}
+
+/// Blocks stored in local JSON files, for example saved from an earlier fetch.
+///
+/// The blocks are served as stored: they are expected to hold what the query that fetched them
+/// selected, and only the block range of a fetch is applied.
+///
+/// # Examples
+///
+/// no_run
+/// let blocks = datasource.get_data_in_range(query.clone(), 100, 200).await?;
+/// FileSource::save("blocks.json", &blocks)?;
+///
+/// let source = FileSource::open("blocks.json")?;
+/// let df = source::get_as_df(&source, query, 150, 200).await?;
+///
+#[derive(Clone, Debug, Default)]
+pub struct FileSource {
+    blocks: Vec<Value>,
+}
+
+impl FileSource {
+    /// Creates a source serving `blocks`.
+    pub fn new(mut blocks: Vec<Value>) -> Self {
+        blocks.sort_by_key(block_number);
+        Self { blocks }
+    }
+
+    /// Loads blocks from a JSON file holding an array of blocks, or from every `.json` file in a
+    /// directory.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut blocks = Vec::new();
+        if path.is_dir() {
+            for entry in fs::read_dir(path)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "json") {
+                    blocks.extend(read_blocks(&path)?);
+                }
+            }
+        } else {
+            blocks = read_blocks(path)?;
+        }
+        Ok(Self::new(blocks))
+    }
+
+    /// Writes `blocks` to a JSON file that `open` can load.
+    pub fn save(path: impl AsRef<Path>, blocks: &[Value]) -> Result<(), Error> {
+        let path = path.as_ref();
+        let contents = serde_json::to_string(blocks)?;
+        fs::write(path, contents).with_context(|| format!("writing blocks to {}", path.display()))
+    }
+}
+
+impl BlockSource for FileSource {
+    fn height(&self) -> BoxFuture<'_, Result<u64, DatasourceError>> {
+        let height = self.blocks.last().map(block_number).unwrap_or(0);
+        Box::pin(async move { Ok(height) })
+    }
+
+    fn fetch_range<'a>(
+        &'a self,
+        _query: &'a Value,
+        start_block: u64,
+        end_block: u64,
+    ) -> BoxFuture<'a, Result<Vec<Value>, DatasourceError>> {
+        Box::pin(async move {
+            let height = self.height().await?;
+            if start_block > height && start_block <= end_block {
+                return Err(DatasourceError::RangeBeyondHeight {
+                    range: BlockRange::new(start_block, end_block),
+                    height,
+                });
+            }
+            let first = self
+                .blocks
+                .partition_point(|block| block_number(block) < start_block);
+            let blocks = self.blocks[first..]
+                .iter()
+                .take_while(|block| block_number(block) <= end_block)
+                .cloned()
+                .collect();
+            Ok(blocks)
+        })
+    }
+}
+
+fn read_blocks(path: &Path) -> Result<Vec<Value>, Error> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("reading blocks from {}", path.display()))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("parsing blocks from {}", path.display()))
+}
+
+fn block_number(block: &Value) -> u64 {
+    block["header"]["number"].as_u64().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasource::DatasourceConfig;
+    use crate::mock_archive::MockArchive;
+    use futures::StreamExt;
+    use serde_json::json;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/blocks.json");
+
+    #[tokio::test]
+    async fn test_file_source_serves_ranges() {
+        let source = FileSource::open(FIXTURE).unwrap();
+        assert_eq!(source.height().await.unwrap(), 14000029);
+
+        let blocks = source
+            .fetch_range(&json!({}), 14000005, 14000009)
+            .await
+            .unwrap();
+        let numbers: Vec<u64> = blocks.iter().map(block_number).collect();
+        assert_eq!(numbers, (14000005..=14000009).collect::<Vec<_>>());
+
+        let batches: Vec<_> = stream_range(&source, json!({}), 14000000, 14000029, 8)
+            .collect()
+            .await;
+        assert_eq!(batches.len(), 4);
+        assert!(matches!(
+            source.fetch_range(&json!({}), 14000030, 14000040).await,
+            Err(DatasourceError::RangeBeyondHeight { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sources_are_interchangeable() {
+        let server = MockArchive::from_fixture(FIXTURE)
+            .unwrap()
+            .with_worker_range_size(10)
+            .start()
+            .await
+            .unwrap();
+        let datasource = Datasource::new(DatasourceConfig::new(server.url(), 2));
+        let query = json!({
+            "logs": [{"address": ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"]}],
+            "fields": {"log": {"address": true, "topics": true, "data": true}},
+        });
+
+        let path = std::env::temp_dir().join(format!("dive-source-{}.json", std::process::id()));
+        let blocks = datasource
+            .fetch_range(&query, 14000000, 14000029)
+            .await
+            .unwrap();
+        FileSource::save(&path, &blocks).unwrap();
+        let file_source = FileSource::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let sources: Vec<Box<dyn BlockSource>> = vec![Box::new(datasource), Box::new(file_source)];
+        for source in &sources {
+            let df = get_as_df(source.as_ref(), query.clone(), 14000010, 14000019)
+                .await
+                .unwrap();
+            let frames: Vec<DataFrame> =
+                stream_as_df(source.as_ref(), query.clone(), 14000000, 14000029, 10)
+                    .try_collect()
+                    .await
+                    .unwrap();
+            assert_eq!(frames.len(), 3);
+            assert_eq!(frames[1], df);
+            assert_eq!(frames.iter().map(|df| df.height()).sum::<usize>(), 27);
+        }
+    }
+}
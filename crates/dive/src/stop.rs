@@ -0,0 +1,147 @@
+//! Cooperative cancellation and deadlines for range fetches.
+//!
+//! The `_until` range methods of `Datasource` take a `StopSignal`. When it fires they stop
+//! cleanly and return what they have fetched so far, together with the block to resume from.
+//! Streams can be stopped the same way with `stream.take_until(stop.stopped())`.
+
+use std::future;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Why a range fetch stopped before reaching its end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The cancellation token was cancelled.
+    Cancelled,
+    /// The deadline passed.
+    DeadlineExceeded,
+}
+
+/// A cancellation token combined with an optional deadline.
+///
+/// # Examples
+///
+/// no_run
+/// let token = CancellationToken::new();
+/// let stop = StopSignal::new()
+///     .with_cancellation(token.clone())
+///     .with_timeout(Duration::from_secs(600));
+/// let fetched = datasource.get_data_in_range_until(query, 0, 20_000_000, &stop).await?;
+/// if !fetched.is_complete() {
+///     println!("stopped, resume from {}", fetched.next_block);
+/// }
+///
+#[derive(Clone, Debug, Default)]
+pub struct StopSignal {
+    token: CancellationToken,
+    deadline: Option<Instant>,
+}
+
+impl StopSignal {
+    /// Creates a signal that only fires when `cancel` is called.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fires when `token` is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Fires once `deadline` has passed.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Fires once `timeout` has passed from now.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// Cancels the underlying token.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// Returns why the signal has fired, or `None` if it has not.
+    pub fn reason(&self) -> Option<StopReason> {
+        if self.token.is_cancelled() {
+            Some(StopReason::Cancelled)
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Some(StopReason::DeadlineExceeded)
+        } else {
+            None
+        }
+    }
+
+    /// Completes when the signal fires.
+    pub async fn stopped(&self) -> StopReason {
+        // An elapsed deadline is only noticed by the timer on its next tick; check it up front.
+        if let Some(reason) = self.reason() {
+            return reason;
+        }
+        let deadline = async {
+            match self.deadline {
+                Some(deadline) => time::sleep_until(deadline).await,
+                None => future::pending().await,
+            }
+        };
+        tokio::select! {
+            biased;
+            _ = self.token.cancelled() => StopReason::Cancelled,
+            _ = deadline => StopReason::DeadlineExceeded,
+        }
+    }
+}
+
+/// The result of a range fetch that may have been stopped early.
+#[derive(Clone, Debug)]
+pub struct PartialRange<T> {
+    /// Everything fetched before the fetch ended.
+    pub data: T,
+    /// The first block that has not been fetched, where a resumed fetch should start. Past the
+    /// end of the range if the fetch completed.
+    pub next_block: u64,
+    /// Why the fetch stopped early, or `None` if it completed.
+    pub stopped: Option<StopReason>,
+}
+
+impl<T> PartialRange<T> {
+    /// Returns `true` if the whole range was fetched.
+    pub fn is_complete(&self) -> bool {
+        self.stopped.is_none()
+    }
+
+    /// Applies `f` to the fetched data, keeping the resume position.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> PartialRange<U> {
+        PartialRange {
+            data: f(self.data),
+            next_block: self.next_block,
+            stopped: self.stopped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_signal_reports_reason() {
+        let stop = StopSignal::new();
+        assert_eq!(stop.reason(), None);
+        stop.cancel();
+        assert_eq!(stop.reason(), Some(StopReason::Cancelled));
+        assert_eq!(stop.stopped().await, StopReason::Cancelled);
+
+        let stop = StopSignal::new().with_timeout(Duration::from_millis(10));
+        assert_eq!(stop.stopped().await, StopReason::DeadlineExceeded);
+        assert_eq!(stop.reason(), Some(StopReason::DeadlineExceeded));
+    }
+}
@@ -0,0 +1,212 @@
+//! Metrics emitted by `Datasource`.
+//!
+//! Metrics go through the `metrics` facade, so any recorder can collect them, for example
+//! `metrics-exporter-prometheus`. Nothing is recorded until the application installs a recorder.
+//!
+//! | Name | Kind | Labels |
+//! |------|------|--------|
+//! | `dive_requests_total` | counter | `endpoint` (`height`, `worker`, `query`), `status` |
+//! | `dive_worker_latency_seconds` | histogram | |
+//! | `dive_bytes_received_total` | counter | |
+//! | `dive_blocks_total` | counter | |
+//! | `dive_items_total` | counter | `dataset` |
+//! | `dive_retries_total` | counter | |
+//! | `dive_semaphore_wait_seconds` | histogram | |
+//!
+//! `status` is the HTTP status code of failed requests, `ok` for successful ones and `error` for
+//! requests that got no response.
+
+use crate::error::DatasourceError;
+use metrics::{counter, describe_counter, describe_histogram, histogram, Unit};
+use serde_json::Value;
+use std::time::Duration;
+
+pub const REQUESTS: &str = "dive_requests_total";
+pub const WORKER_LATENCY: &str = "dive_worker_latency_seconds";
+pub const BYTES_RECEIVED: &str = "dive_bytes_received_total";
+pub const BLOCKS: &str = "dive_blocks_total";
+pub const ITEMS: &str = "dive_items_total";
+pub const RETRIES: &str = "dive_retries_total";
+pub const SEMAPHORE_WAIT: &str = "dive_semaphore_wait_seconds";
+
+/// Registers descriptions and units for all metrics with the installed recorder. Call it once
+/// after installing the recorder.
+///
+/// # Examples
+///
+/// no_run
+/// PrometheusBuilder::new().install()?;
+/// dive::telemetry::describe_metrics();
+///
+pub fn describe_metrics() {
+    describe_counter!(
+        REQUESTS,
+        "Router and worker requests by endpoint and status"
+    );
+    describe_histogram!(
+        WORKER_LATENCY,
+        Unit::Seconds,
+        "Time workers took to answer queries"
+    );
+    describe_counter!(
+        BYTES_RECEIVED,
+        Unit::Bytes,
+        "Response bytes received from workers"
+    );
+    describe_counter!(BLOCKS, "Blocks scanned by worker queries");
+    describe_counter!(ITEMS, "Items received per dataset");
+    describe_counter!(RETRIES, "Failed requests that were retried");
+    describe_histogram!(
+        SEMAPHORE_WAIT,
+        Unit::Seconds,
+        "Time spent waiting for a concurrency permit"
+    );
+}
+
+/// The archive endpoints requests are counted by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Endpoint {
+    Height,
+    Worker,
+    Query,
+}
+
+impl Endpoint {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Height => "height",
+            Self::Worker => "worker",
+            Self::Query => "query",
+        }
+    }
+}
+
+/// Counts a request to `endpoint` by the outcome of the transport call.
+pub(crate) fn record_request<T>(endpoint: Endpoint, result: &Result<T, DatasourceError>) {
+    let status = match result {
+        Ok(_) => "ok".to_string(),
+        Err(DatasourceError::Http { status, .. }) => status.as_str().to_string(),
+        Err(_) => "error".to_string(),
+    };
+    counter!(REQUESTS, "endpoint" => endpoint.as_str(), "status" => status).increment(1);
+}
+
+/// Records a successful worker response.
+pub(crate) fn record_worker_response(latency: Duration, bytes: usize) {
+    histogram!(WORKER_LATENCY).record(latency);
+    counter!(BYTES_RECEIVED).increment(bytes as u64);
+}
+
+/// Counts the blocks a batch scanned and the items it holds per dataset.
+pub(crate) fn record_batch(blocks_scanned: u64, blocks: &[Value]) {
+    counter!(BLOCKS).increment(blocks_scanned);
+    for (dataset, items) in dataset_items(blocks) {
+        counter!(ITEMS, "dataset" => dataset.to_string()).increment(items as u64);
+    }
+}
+
+pub(crate) fn record_retry() {
+    counter!(RETRIES).increment(1);
+}
+
+pub(crate) fn record_semaphore_wait(wait: Duration) {
+    histogram!(SEMAPHORE_WAIT).record(wait);
+}
+
+/// Yields every dataset array in `blocks`, such as `logs` or `transactions`, with its length.
+pub(crate) fn dataset_items(blocks: &[Value]) -> impl Iterator<Item = (&str, usize)> {
+    blocks
+        .iter()
+        .filter_map(Value::as_object)
+        .flat_map(|block| block.iter())
+        .filter_map(|(dataset, items)| Some((dataset.as_str(), items.as_array()?.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasource::{Datasource, DatasourceConfig};
+    use crate::mock_archive::{Endpoint as MockEndpoint, Failure, MockArchive};
+    use crate::retry::RetryPolicy;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use metrics_util::MetricKind;
+    use serde_json::json;
+
+    #[test]
+    fn test_fetch_records_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/blocks.json");
+                let server = MockArchive::from_fixture(fixture)
+                    .unwrap()
+                    .with_worker_range_size(10)
+                    .with_max_blocks_per_response(4)
+                    .with_failure(
+                        MockEndpoint::Query,
+                        Failure::Status(503, "busy".to_string()),
+                    )
+                    .start()
+                    .await
+                    .unwrap();
+                let config =
+                    DatasourceConfig::new(server.url(), 2).with_retry_policy(RetryPolicy {
+                        base_delay: Duration::ZERO,
+                        ..Default::default()
+                    });
+                let query = json!({
+                    "logs": [{"address": ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"]}],
+                    "fields": {"log": {"address": true}},
+                });
+                Datasource::new(config)
+                    .get_data_in_range(query, 14000000, 14000009)
+                    .await
+                    .unwrap();
+            });
+        });
+
+        let metrics: Vec<_> = snapshotter.snapshot().into_vec();
+        let counter = |name: &str, labels: &[(&str, &str)]| -> u64 {
+            metrics
+                .iter()
+                .filter(|(key, ..)| key.kind() == MetricKind::Counter && key.key().name() == name)
+                .filter(|(key, ..)| {
+                    labels.iter().all(|(label, value)| {
+                        key.key()
+                            .labels()
+                            .any(|l| l.key() == *label && l.value() == *value)
+                    })
+                })
+                .map(|(.., value)| match value {
+                    DebugValue::Counter(count) => *count,
+                    _ => 0,
+                })
+                .sum()
+        };
+
+        assert_eq!(
+            counter(REQUESTS, &[("endpoint", "query"), ("status", "503")]),
+            1
+        );
+        assert_eq!(
+            counter(REQUESTS, &[("endpoint", "query"), ("status", "ok")]),
+            3
+        );
+        assert_eq!(counter(RETRIES, &[]), 1);
+        assert!(counter(BLOCKS, &[]) >= 10);
+        assert_eq!(counter(ITEMS, &[("dataset", "logs")]), 10);
+        assert!(counter(BYTES_RECEIVED, &[]) > 0);
+        assert!(metrics
+            .iter()
+            .any(|(key, ..)| key.key().name() == WORKER_LATENCY));
+        assert!(metrics
+            .iter()
+            .any(|(key, ..)| key.key().name() == SEMAPHORE_WAIT));
+    }
+}
@@ -0,0 +1,280 @@
+use crate::error::{BlockRange, DatasourceError};
+use futures::future::BoxFuture;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// The HTTP calls `Datasource` makes to a Subsquid archive.
+///
+/// Each method returns the body of a successful response. Non-success statuses are returned as
+/// `DatasourceError::Http` so `Datasource` can classify and retry them.
+pub trait ArchiveTransport: Send + Sync {
+    /// `GET {base_url}/height`.
+    fn height<'a>(&'a self, base_url: &'a str) -> BoxFuture<'a, Result<String, DatasourceError>>;
+
+    /// `GET {base_url}/{block}/worker`.
+    fn worker<'a>(
+        &'a self,
+        base_url: &'a str,
+        block: u64,
+    ) -> BoxFuture<'a, Result<String, DatasourceError>>;
+
+    /// `POST {worker_url}` with the query as JSON.
+    fn query<'a>(
+        &'a self,
+        worker_url: &'a str,
+        query: &'a Value,
+    ) -> BoxFuture<'a, Result<String, DatasourceError>>;
+}
+
+/// URL of the router's dataset height endpoint.
+pub fn height_url(base_url: &str) -> String {
+    format!("{}/height", base_url)
+}
+
+/// URL of the router's worker lookup endpoint for a block.
+pub fn worker_lookup_url(base_url: &str, block: u64) -> String {
+    format!("{}/{}/worker", base_url, block)
+}
+
+/// The default transport, talking to the archive over HTTP with `reqwest`.
+#[derive(Clone, Debug, Default)]
+pub struct ReqwestTransport {
+    client: Client,
+}
+
+impl ReqwestTransport {
+    /// Creates a transport that sends requests with the given client.
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Sends a request and returns the response body, failing on non-success statuses.
+    pub(crate) async fn send(
+        request: RequestBuilder,
+        url: &str,
+        range: Option<BlockRange>,
+    ) -> Result<String, DatasourceError> {
+        let request_error = |source| DatasourceError::Request {
+            url: url.to_string(),
+            range,
+            source,
+        };
+        let response = request.send().await.map_err(request_error)?;
+        let status = response.status();
+        let body = response.text().await.map_err(request_error)?;
+
+        if !status.is_success() {
+            return Err(DatasourceError::Http {
+                status,
+                body,
+                url: url.to_string(),
+                range,
+            });
+        }
+        Ok(body)
+    }
+}
+
+impl ArchiveTransport for ReqwestTransport {
+    fn height<'a>(&'a self, base_url: &'a str) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        Box::pin(async move {
+            let url = height_url(base_url);
+            Self::send(self.client.get(&url), &url, None).await
+        })
+    }
+
+    fn worker<'a>(
+        &'a self,
+        base_url: &'a str,
+        block: u64,
+    ) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        Box::pin(async move {
+            let url = worker_lookup_url(base_url, block);
+            let range = Some(BlockRange::starting_at(block));
+            Self::send(self.client.get(&url), &url, range).await
+        })
+    }
+
+    fn query<'a>(
+        &'a self,
+        worker_url: &'a str,
+        query: &'a Value,
+    ) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        Box::pin(async move {
+            let range = BlockRange::of_query(query);
+            Self::send(self.client.post(worker_url).json(query), worker_url, range).await
+        })
+    }
+}
+
+/// A scripted response served by `MockTransport`.
+#[derive(Clone, Debug)]
+pub enum MockResponse {
+    /// A successful response with this body.
+    Body(String),
+    /// A failed response with this status and body.
+    Status(StatusCode, String),
+}
+
+impl MockResponse {
+    /// A successful response whose body is the given JSON.
+    pub fn json(value: Value) -> Self {
+        Self::Body(value.to_string())
+    }
+}
+
+/// A request received by `MockTransport`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MockRequest {
+    Height,
+    Worker { block: u64 },
+    Query { worker_url: String, query: Value },
+}
+
+/// An in-memory transport that replays scripted responses, for running `Datasource` offline.
+///
+/// Responses are queued per endpoint and served in order. A call with nothing queued fails with
+/// `404 Not Found`. Every request is recorded and can be inspected with `requests`.
+///
+/// # Examples
+///
+/// no_run
+/// let transport = Arc::new(MockTransport::new());
+/// transport.push_height(MockResponse::json(json!(100)));
+/// let datasource = Datasource::with_transport(config, transport.clone());
+///
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    heights: Mutex<VecDeque<MockResponse>>,
+    workers: Mutex<VecDeque<MockResponse>>,
+    queries: Mutex<VecDeque<MockResponse>>,
+    requests: Mutex<Vec<MockRequest>>,
+}
+
+impl MockTransport {
+    /// Creates a transport with no scripted responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a response for the next `/height` call.
+    pub fn push_height(&self, response: MockResponse) {
+        self.heights.lock().unwrap().push_back(response);
+    }
+
+    /// Queues a response for the next `/worker` call.
+    pub fn push_worker(&self, response: MockResponse) {
+        self.workers.lock().unwrap().push_back(response);
+    }
+
+    /// Queues a response for the next worker query.
+    pub fn push_query(&self, response: MockResponse) {
+        self.queries.lock().unwrap().push_back(response);
+    }
+
+    /// Returns the requests received so far, in order.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn respond(
+        &self,
+        request: MockRequest,
+        url: String,
+        range: Option<BlockRange>,
+    ) -> Result<String, DatasourceError> {
+        let queue = match request {
+            MockRequest::Height => &self.heights,
+            MockRequest::Worker { .. } => &self.workers,
+            MockRequest::Query { .. } => &self.queries,
+        };
+        self.requests.lock().unwrap().push(request);
+
+        let response = queue.lock().unwrap().pop_front().unwrap_or_else(|| {
+            MockResponse::Status(StatusCode::NOT_FOUND, "no scripted response".to_string())
+        });
+        match response {
+            MockResponse::Body(body) => Ok(body),
+            MockResponse::Status(status, body) => Err(DatasourceError::Http {
+                status,
+                body,
+                url,
+                range,
+            }),
+        }
+    }
+}
+
+impl ArchiveTransport for MockTransport {
+    fn height<'a>(&'a self, base_url: &'a str) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        let response = self.respond(MockRequest::Height, height_url(base_url), None);
+        Box::pin(async move { response })
+    }
+
+    fn worker<'a>(
+        &'a self,
+        base_url: &'a str,
+        block: u64,
+    ) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        let response = self.respond(
+            MockRequest::Worker { block },
+            worker_lookup_url(base_url, block),
+            Some(BlockRange::starting_at(block)),
+        );
+        Box::pin(async move { response })
+    }
+
+    fn query<'a>(
+        &'a self,
+        worker_url: &'a str,
+        query: &'a Value,
+    ) -> BoxFuture<'a, Result<String, DatasourceError>> {
+        let request = MockRequest::Query {
+            worker_url: worker_url.to_string(),
+            query: query.clone(),
+        };
+        let response = self.respond(request, worker_url.to_string(), BlockRange::of_query(query));
+        Box::pin(async move { response })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_mock_transport_replays_in_order() {
+        let transport = MockTransport::new();
+        transport.push_worker(MockResponse::Body("http://worker-1".to_string()));
+        transport.push_worker(MockResponse::Status(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "busy".to_string(),
+        ));
+
+        assert_eq!(
+            transport.worker("http://router", 5).await.unwrap(),
+            "http://worker-1"
+        );
+        let error = transport.worker("http://router", 6).await.unwrap_err();
+        assert!(error.is_retryable());
+        assert!(transport.worker("http://router", 7).await.is_err());
+
+        let query = json!({"fromBlock": 5});
+        assert!(transport.query("http://worker-1", &query).await.is_err());
+        assert_eq!(
+            transport.requests(),
+            vec![
+                MockRequest::Worker { block: 5 },
+                MockRequest::Worker { block: 6 },
+                MockRequest::Worker { block: 7 },
+                MockRequest::Query {
+                    worker_url: "http://worker-1".to_string(),
+                    query,
+                },
+            ]
+        );
+    }
+}
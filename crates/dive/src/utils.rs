@@ -1,5 +1,6 @@
 use reqwest::{self};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 
 pub async fn get_height(archive_url: &str) -> Result<String, reqwest::Error> {
     let url = format!("{}/height", archive_url);
@@ -20,10 +21,73 @@ pub fn add_from_block(mut json_value: Value, from_block_value: u64) -> Value {
     json_value
 }
 
+pub fn add_to_block(mut json_value: Value, to_block_value: u64) -> Value {
+    if let Value::Object(ref mut map) = json_value {
+        map.insert("toBlock".to_string(), to_block_value.into());
+    }
+    json_value
+}
+
+/// Serializes JSON with object keys sorted at every level, so equal queries always produce the
+/// same text regardless of how they were built.
+pub fn canonical_json(value: &Value) -> String {
+    fn sorted(value: &Value) -> Value {
+        match value {
+            Value::Object(map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by_key(|(key, _)| *key);
+                Value::Object(
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| (key.clone(), sorted(value)))
+                        .collect(),
+                )
+            }
+            Value::Array(items) => Value::Array(items.iter().map(sorted).collect()),
+            other => other.clone(),
+        }
+    }
+    sorted(value).to_string()
+}
+
+/// Returns a stable hex SHA-256 hash of the query's canonical JSON.
+pub fn query_hash(query: &Value) -> String {
+    let digest = Sha256::digest(canonical_json(query).as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Splits the inclusive block range `[start, end]` into consecutive inclusive
+/// ranges of at most `chunk_size` blocks.
 pub fn compute_chunk_ranges(start: u64, end: u64, chunk_size: u64) -> Vec<(u64, u64)> {
-    // Divide the total range into smaller ranges of chunk_size
-    (start..end)
+    let chunk_size = chunk_size.max(1);
+    (start..=end)
         .step_by(chunk_size as usize)
-        .map(|start| (start, std::cmp::min(start + chunk_size, end)))
+        .map(|start| (start, std::cmp::min(start + chunk_size - 1, end)))
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_chunk_ranges() {
+        assert_eq!(
+            compute_chunk_ranges(0, 25, 10),
+            vec![(0, 9), (10, 19), (20, 25)]
+        );
+        assert_eq!(compute_chunk_ranges(5, 5, 10), vec![(5, 5)]);
+        assert!(compute_chunk_ranges(10, 5, 10).is_empty());
+    }
+
+    #[test]
+    fn test_query_hash_ignores_key_order() {
+        let a: Value = serde_json::from_str(r#"{"logs": [{"address": ["0x1"], "topic0": ["0x2"]}], "fields": {"log": {"data": true}}}"#).unwrap();
+        let b: Value = serde_json::from_str(r#"{"fields": {"log": {"data": true}}, "logs": [{"topic0": ["0x2"], "address": ["0x1"]}]}"#).unwrap();
+        let c: Value = serde_json::from_str(r#"{"logs": [{"address": ["0x3"]}]}"#).unwrap();
+
+        assert_eq!(query_hash(&a), query_hash(&b));
+        assert_ne!(query_hash(&a), query_hash(&c));
+        assert_eq!(query_hash(&a).len(), 64);
+    }
+}
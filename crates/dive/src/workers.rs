@@ -0,0 +1,321 @@
+//! Worker reuse and health tracking.
+//!
+//! The router assigns every block to a worker, and a worker serves a long run of consecutive
+//! blocks. `WorkerPool` remembers which worker served which blocks so the next batch can go
+//! straight to the same worker, and keeps per-worker health stats so failing workers are avoided
+//! for a cooldown. Worker ranges found by `Datasource::discover_worker_ranges` are kept here too.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Request counts, latency and cooldown of one worker.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorkerStats {
+    pub requests: u64,
+    pub failures: u64,
+    pub total_latency: Duration,
+    pub cooldown_until: Option<Instant>,
+}
+
+impl WorkerStats {
+    /// The mean time the worker took to answer, or `None` before its first request.
+    pub fn average_latency(&self) -> Option<Duration> {
+        u32::try_from(self.requests)
+            .ok()
+            .filter(|&requests| requests > 0)
+            .map(|requests| self.total_latency / requests)
+    }
+
+    /// The fraction of requests to the worker that failed.
+    pub fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            self.failures as f64 / self.requests as f64
+        }
+    }
+
+    /// Returns `true` if the worker should not be sent requests at `now`.
+    pub fn is_cooling_down(&self, now: Instant) -> bool {
+        self.cooldown_until.is_some_and(|until| now < until)
+    }
+}
+
+/// The blocks a worker serves, as discovered by querying it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorkerRange {
+    pub worker_url: String,
+    pub start_block: u64,
+    pub end_block: u64,
+}
+
+impl WorkerRange {
+    pub fn contains(&self, block: u64) -> bool {
+        self.start_block <= block && block <= self.end_block
+    }
+}
+
+/// Blocks `from..=to` were served by `worker_url`. While `open`, the worker is assumed to also
+/// serve the block right after `to`.
+#[derive(Debug)]
+struct Assignment {
+    worker_url: String,
+    from: u64,
+    to: u64,
+    open: bool,
+}
+
+impl Assignment {
+    fn covers(&self, block: u64) -> bool {
+        self.from <= block && (block <= self.to || (self.open && block == self.to + 1))
+    }
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    assignments: Vec<Assignment>,
+    stats: HashMap<String, WorkerStats>,
+    /// Discovered worker ranges by their first block, and whether each is open.
+    ranges: BTreeMap<u64, (WorkerRange, bool)>,
+}
+
+/// Worker assignments and health shared by the requests of a `Datasource`.
+#[derive(Debug)]
+pub struct WorkerPool {
+    cooldown: Duration,
+    state: Mutex<PoolState>,
+}
+
+impl WorkerPool {
+    /// Creates an empty pool that rests a worker for `cooldown` after a failed request.
+    pub fn new(cooldown: Duration) -> Self {
+        Self {
+            cooldown,
+            state: Mutex::new(PoolState::default()),
+        }
+    }
+
+    /// Returns a healthy worker known to serve `block`, or the block right after the ones it
+    /// served last.
+    pub fn lookup(&self, block: u64) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state
+            .assignments
+            .iter()
+            .filter(|assignment| assignment.covers(block))
+            .filter(|assignment| {
+                !state
+                    .stats
+                    .get(&assignment.worker_url)
+                    .is_some_and(|stats| stats.is_cooling_down(now))
+            })
+            .max_by_key(|assignment| assignment.to)
+            .map(|assignment| assignment.worker_url.clone())
+    }
+
+    /// Returns `true` if `worker_url` failed recently and is resting.
+    pub fn is_cooling_down(&self, worker_url: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .stats
+            .get(worker_url)
+            .is_some_and(|stats| stats.is_cooling_down(Instant::now()))
+    }
+
+    /// Records that `worker_url` answered with blocks `from..=to` after `latency`.
+    pub fn record_success(&self, worker_url: &str, from: u64, to: u64, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let stats = state.stats.entry(worker_url.to_string()).or_default();
+        stats.requests += 1;
+        stats.total_latency += latency;
+        stats.cooldown_until = None;
+
+        let existing = state
+            .assignments
+            .iter_mut()
+            .find(|assignment| assignment.worker_url == worker_url && assignment.covers(from));
+        match existing {
+            Some(assignment) => {
+                assignment.to = assignment.to.max(to);
+                assignment.open = true;
+            }
+            None => state.assignments.push(Assignment {
+                worker_url: worker_url.to_string(),
+                from,
+                to,
+                open: true,
+            }),
+        }
+    }
+
+    /// Records that `range.worker_url` serves `range.start_block..=range.end_block`. Requests for
+    /// those blocks go straight to that worker. While `open`, the range was cut short rather than
+    /// found to end at `range.end_block`, and the worker may serve the blocks after it too.
+    ///
+    /// Recording a range again, for example once an open range has been extended, replaces the
+    /// worker's assignment that starts at the same block.
+    pub fn record_range(&self, range: WorkerRange, open: bool) {
+        let mut state = self.state.lock().unwrap();
+        let existing = state.assignments.iter_mut().find(|assignment| {
+            assignment.worker_url == range.worker_url && assignment.from == range.start_block
+        });
+        match existing {
+            Some(assignment) => {
+                assignment.to = range.end_block;
+                assignment.open = open;
+            }
+            None => state.assignments.push(Assignment {
+                worker_url: range.worker_url.clone(),
+                from: range.start_block,
+                to: range.end_block,
+                open,
+            }),
+        }
+        state.ranges.insert(range.start_block, (range, open));
+    }
+
+    /// Returns the discovered worker range that contains `block`, and whether it is open.
+    pub fn known_range(&self, block: u64) -> Option<(WorkerRange, bool)> {
+        let state = self.state.lock().unwrap();
+        state
+            .ranges
+            .range(..=block)
+            .next_back()
+            .map(|(_, range)| range)
+            .filter(|(range, _)| range.contains(block))
+            .cloned()
+    }
+
+    /// Records a failed request to `worker_url`. With `cooldown`, the worker is not reused until
+    /// the cooldown has passed.
+    pub fn record_failure(&self, worker_url: &str, latency: Duration, cooldown: bool) {
+        let mut state = self.state.lock().unwrap();
+        let stats = state.stats.entry(worker_url.to_string()).or_default();
+        stats.requests += 1;
+        stats.failures += 1;
+        stats.total_latency += latency;
+        if cooldown {
+            stats.cooldown_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    /// Records that `worker_url` does not serve `block`, so its assignment ends before it. The
+    /// worker's range ending is not a failure, so its stats are left alone.
+    pub fn close_assignment(&self, worker_url: &str, block: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.assignments.retain_mut(|assignment| {
+            if assignment.worker_url != worker_url || !assignment.covers(block) {
+                return true;
+            }
+            if block <= assignment.from {
+                return false;
+            }
+            assignment.to = assignment.to.min(block - 1);
+            assignment.open = false;
+            true
+        });
+        state
+            .ranges
+            .retain(|_, (range, _)| range.worker_url != worker_url || !range.contains(block));
+    }
+
+    /// Returns the stats of every worker that has been sent a request.
+    pub fn stats(&self) -> HashMap<String, WorkerStats> {
+        self.state.lock().unwrap().stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_follows_served_blocks() {
+        let pool = WorkerPool::new(Duration::from_secs(30));
+        assert_eq!(pool.lookup(10), None);
+
+        pool.record_success("http://w1", 10, 19, Duration::from_millis(100));
+        assert_eq!(pool.lookup(15).as_deref(), Some("http://w1"));
+        assert_eq!(pool.lookup(20).as_deref(), Some("http://w1"));
+        assert_eq!(pool.lookup(21), None);
+
+        pool.close_assignment("http://w1", 20);
+        assert_eq!(pool.lookup(20), None);
+        assert_eq!(pool.lookup(19).as_deref(), Some("http://w1"));
+        let stats = &pool.stats()["http://w1"];
+        assert_eq!((stats.requests, stats.failures), (1, 0));
+    }
+
+    #[test]
+    fn test_discovered_ranges() {
+        let pool = WorkerPool::new(Duration::from_secs(30));
+        pool.record_range(
+            WorkerRange {
+                worker_url: "http://w1".to_string(),
+                start_block: 10,
+                end_block: 19,
+            },
+            false,
+        );
+        assert_eq!(pool.known_range(15).unwrap().0.start_block, 10);
+        assert_eq!(pool.known_range(20), None);
+        assert_eq!(pool.known_range(5), None);
+        assert_eq!(pool.lookup(10).as_deref(), Some("http://w1"));
+        assert_eq!(pool.lookup(20), None);
+
+        pool.close_assignment("http://w1", 12);
+        assert_eq!(pool.known_range(15), None);
+        assert_eq!(pool.lookup(11).as_deref(), Some("http://w1"));
+    }
+
+    #[test]
+    fn test_open_range_may_continue() {
+        let pool = WorkerPool::new(Duration::from_secs(30));
+        let range = WorkerRange {
+            worker_url: "http://w1".to_string(),
+            start_block: 10,
+            end_block: 19,
+        };
+        pool.record_range(range.clone(), true);
+        assert_eq!(pool.known_range(19), Some((range.clone(), true)));
+        assert_eq!(pool.known_range(20), None);
+        assert_eq!(pool.lookup(20).as_deref(), Some("http://w1"));
+
+        // Extending the range updates its assignment rather than adding another.
+        let extended = WorkerRange {
+            end_block: 29,
+            ..range
+        };
+        pool.record_range(extended.clone(), false);
+        assert_eq!(pool.known_range(25), Some((extended, false)));
+        assert_eq!(pool.lookup(30), None);
+        assert_eq!(pool.state.lock().unwrap().assignments.len(), 1);
+    }
+
+    #[test]
+    fn test_failed_worker_cools_down() {
+        let pool = WorkerPool::new(Duration::from_secs(30));
+        pool.record_success("http://w1", 10, 19, Duration::from_millis(100));
+        pool.record_failure("http://w1", Duration::from_millis(300), true);
+
+        assert!(pool.is_cooling_down("http://w1"));
+        assert_eq!(pool.lookup(20), None);
+
+        let stats = &pool.stats()["http://w1"];
+        assert_eq!(stats.requests, 2);
+        assert_eq!(stats.error_rate(), 0.5);
+        assert_eq!(stats.average_latency(), Some(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn test_cooldown_expires() {
+        let pool = WorkerPool::new(Duration::ZERO);
+        pool.record_success("http://w1", 10, 19, Duration::ZERO);
+        pool.record_failure("http://w1", Duration::ZERO, true);
+
+        assert!(!pool.is_cooling_down("http://w1"));
+        assert_eq!(pool.lookup(20).as_deref(), Some("http://w1"));
+    }
+}
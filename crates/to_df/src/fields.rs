@@ -2,9 +2,8 @@ use std::collections::HashMap;
 
 use anyhow::Error;
 use anyhow::Result;
-use polars::prelude::{NamedFrom, Series};
+use polars::prelude::{DataType, NamedFrom, Series};
 use serde_json::Value;
-use std::collections::HashSet;
 
 pub fn hex_str_to_u64(hex_str: &str) -> Result<u64, std::num::ParseIntError> {
     let trimmed_hex_str = hex_str.trim_start_matches("0x");
@@ -424,47 +423,27 @@ pub fn create_columns_from_field_data(
         //     } //check this later
         // },
         Some(FieldData::LogsData(data)) => match data {
-            LogFieldData::Id(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
-                }
-            }
-            LogFieldData::LogIndex(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
-                }
-            }
-            LogFieldData::TransactionIndex(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
-                }
-            }
-            LogFieldData::TransactionHash(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
-                }
-            }
-            //LogFieldData::BlockHash(vec) => if !vec.is_empty() { columns.push(Series::new(*field, vec)) },
-            LogFieldData::Address(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
-                }
-            }
-            LogFieldData::Data(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
-                }
-            }
+            LogFieldData::Id(vec) => columns.push(Series::new(*field, vec)),
+            LogFieldData::LogIndex(vec) => columns.push(Series::new(*field, vec)),
+            LogFieldData::TransactionIndex(vec) => columns.push(Series::new(*field, vec)),
+            LogFieldData::TransactionHash(vec) => columns.push(Series::new(*field, vec)),
+            //LogFieldData::BlockHash(vec) => columns.push(Series::new(*field, vec)),
+            LogFieldData::Address(vec) => columns.push(Series::new(*field, vec)),
+            LogFieldData::Data(vec) => columns.push(Series::new(*field, vec)),
             LogFieldData::Topics(vec) => {
+                // One entry per log; anonymous logs get an empty list
                 let series_list: Vec<_> = vec
                     .into_iter()
-                    .filter(|v| !v.is_empty()) // Filter out empty vectors
                     .map(|v| {
                         // Convert Vec<String> to Series
                         Series::new("inner_series", v)
                     })
                     .collect();
-                if !series_list.is_empty() {
+                if series_list.is_empty() {
+                    // Keep the column, typed, so empty batches have the same schema
+                    let dtype = DataType::List(Box::new(DataType::String));
+                    columns.push(Series::new_empty("topics", &dtype));
+                } else {
                     // Convert the list of Series into a ListChunked
                     let list_series = Series::new("topics", series_list);
                     columns.push(list_series);
@@ -491,27 +470,34 @@ pub fn extract_fields<'a>(query: &'a Value) -> Vec<&'a str> {
     }
     Vec::new()
 }
-pub fn get_dataset<'a>(query: &'a Value) -> Dataset {
+/// Returns the dataset the query's DataFrame is built from, or an error if the query requests
+/// none that can be converted, such as traces only.
+pub fn get_dataset<'a>(query: &'a Value) -> Result<Dataset> {
     if let Some(dataset) = query.get("transactions") {
-        return Dataset::Transactions;
+        return Ok(Dataset::Transactions);
     }
     if let Some(dataset) = query.get("blocks") {
-        return Dataset::Blocks;
+        return Ok(Dataset::Blocks);
     }
     if let Some(dataset) = query.get("logs") {
-        return Dataset::Logs;
+        return Ok(Dataset::Logs);
     }
 
-    panic!("Dataset not found");
+    Err(Error::msg(
+        "query requests no dataset that converts to a DataFrame (blocks, transactions or logs)",
+    ))
 }
+/// Returns the selected (`true`) field names sorted by name, so every DataFrame built from the
+/// same query has the same column order, whatever order the query lists them in.
 pub fn parse_field<'a>(field: &'a Value) -> Vec<&'a str> {
-    let mut set = HashSet::new();
+    let mut selected = Vec::new();
     if let Some(obj) = field.as_object() {
         for (key, value) in obj {
             if let Some(true) = value.as_bool() {
-                set.insert(key.as_str());
+                selected.push(key.as_str());
             }
         }
     }
-    set.into_iter().collect()
+    selected.sort_unstable();
+    selected
 }
@@ -11,12 +11,14 @@ use anyhow::Error;
 use fields::{create_columns_from_field_data, create_field_data, FieldData};
 use std::fs::{self, File};
 use std::path::Path;
+use tracing::{debug_span, trace, warn};
 
 pub fn to_df(
     dataset: Dataset,
     json_data: Vec<Value>,
     fields: Vec<&str>,
 ) -> Result<DataFrame, Error> {
+    let _span = debug_span!("to_df", dataset = ?dataset, blocks = json_data.len()).entered();
     let data_fields: Vec<(&str, FieldData)> = fields
         .iter()
         .filter_map(|&field| {
@@ -48,6 +50,7 @@ fn process_json_object(
     fields: &[&str],
     dataset: &Dataset,
 ) -> Result<HashMap<String, FieldData>, Error> {
+    let mut skipped: HashMap<&str, (usize, Error)> = HashMap::new();
     for json_obj in json_data {
         match dataset {
             Dataset::Blocks => {
@@ -56,9 +59,7 @@ fn process_json_object(
                     fields.iter().for_each(|field| {
                         if let Some(data) = field_map.get_mut(*field) {
                             if let Some(value) = header.get(*field) {
-                                if let Err(e) = data.add_value(value) {
-                                    eprintln!("Error processing value: {}", e);
-                                }
+                                add_or_skip(data, value, field, &mut skipped);
                             }
                         }
                     });
@@ -71,9 +72,7 @@ fn process_json_object(
                         if let Some(data) = field_map.get_mut(*field) {
                             for tx in tx_list.as_array().unwrap() {
                                 if let Some(value) = tx.get(*field) {
-                                    if let Err(e) = data.add_value(value) {
-                                        eprintln!("Error processing value: {}", e);
-                                    }
+                                    add_or_skip(data, value, field, &mut skipped);
                                 }
                             }
                         }
@@ -87,9 +86,7 @@ fn process_json_object(
                         if let Some(data) = field_map.get_mut(*field) {
                             for log in log_list.as_array().unwrap() {
                                 if let Some(value) = log.get(*field) {
-                                    if let Err(e) = data.add_value(value) {
-                                        eprintln!("Error processing value: {}", e);
-                                    }
+                                    add_or_skip(data, value, field, &mut skipped);
                                 }
                             }
                         }
@@ -99,9 +96,28 @@ fn process_json_object(
         }
     }
 
+    for field in fields {
+        if let Some((count, first_error)) = skipped.get(field) {
+            warn!(dataset = ?dataset, field = *field, skipped = *count, first_error = %first_error, "skipped values");
+        }
+    }
     Ok(field_map)
 }
 
+/// Adds `value` to `data`. A value that does not fit is skipped and counted in `skipped`, which
+/// keeps the first error per field.
+fn add_or_skip<'a>(
+    data: &mut FieldData,
+    value: &Value,
+    field: &'a str,
+    skipped: &mut HashMap<&'a str, (usize, Error)>,
+) {
+    if let Err(e) = data.add_value(value) {
+        trace!(field, error = %e, "skipping value");
+        skipped.entry(field).or_insert((0, e)).0 += 1;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -153,6 +169,64 @@ mod tests {
         assert_eq!(df.shape().1, 4); // 4 columns
     }
 
+    #[test]
+    fn test_to_df_logs_empty_batch_keeps_schema() {
+        let fields = vec!["address", "topics", "data"];
+        let full = to_df(
+            Dataset::Logs,
+            vec![json!({"logs": [{"address": "0xabc", "topics": ["0x1"], "data": "0x"}]})],
+            fields.clone(),
+        )
+        .unwrap();
+        let empty = to_df(Dataset::Logs, vec![json!({"logs": []})], fields).unwrap();
+
+        assert_eq!(empty.height(), 0);
+        assert_eq!(full.schema(), empty.schema());
+    }
+
+    #[test]
+    fn test_to_df_logs_without_topics() {
+        let fields = vec!["address", "topics"];
+        let anonymous = to_df(
+            Dataset::Logs,
+            vec![json!({"logs": [
+                {"address": "0xabc", "topics": []},
+                {"address": "0xdef", "topics": []},
+            ]})],
+            fields.clone(),
+        )
+        .unwrap();
+        assert_eq!(anonymous.height(), 2);
+        assert_eq!(
+            anonymous.column("topics").unwrap().dtype(),
+            &polars::prelude::DataType::List(Box::new(polars::prelude::DataType::String))
+        );
+
+        let mixed = to_df(
+            Dataset::Logs,
+            vec![json!({"logs": [
+                {"address": "0xabc", "topics": []},
+                {"address": "0xdef", "topics": ["0x1", "0x2"]},
+            ]})],
+            fields,
+        )
+        .unwrap();
+        assert_eq!(mixed.height(), 2);
+        let topics = mixed.column("topics").unwrap().list().unwrap();
+        assert_eq!(topics.get_as_series(0).unwrap().len(), 0);
+        assert_eq!(topics.get_as_series(1).unwrap().len(), 2);
+        assert_eq!(anonymous.schema(), mixed.schema());
+    }
+
+    #[test]
+    fn test_extract_fields_sorted_by_name() {
+        let query: Value = serde_json::from_str(
+            r#"{"logs": [], "fields": {"log": {"topics": true, "logIndex": false, "data": true, "address": true}}}"#,
+        )
+        .unwrap();
+        assert_eq!(extract_fields(&query), vec!["address", "data", "topics"]);
+    }
+
     /* #[tokio::test]
     async fn test_with_archive() {
         let dataset = Dataset::Logs;